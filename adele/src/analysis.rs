@@ -0,0 +1,360 @@
+//! Facts about the structure of a graph: its strongly connected components,
+//! whether it has cycles, a topological order, and which vertices are sources,
+//! sinks, or can't be reached from any source.
+
+use std::collections::{BTreeSet, HashMap};
+
+use graph::{EdgeId, Graph, VertexId};
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    /// The strongly connected components, each in creation order. The components
+    /// themselves are in topological order: edges between them only go forwards.
+    pub components: Vec<Vec<VertexId>>,
+    /// Every vertex such that every edge goes forwards, or `None` if there's a cycle.
+    /// Vertices that could go in either order stay in creation order.
+    pub topological_order: Option<Vec<VertexId>>,
+    /// Vertices without incoming edges.
+    pub sources: Vec<VertexId>,
+    /// Vertices without outgoing edges.
+    pub sinks: Vec<VertexId>,
+    /// Vertices that no path from a source leads to, which only happens on cycles.
+    pub unreachable: Vec<VertexId>
+}
+
+impl Analysis {
+    pub fn new(graph: &Graph) -> Self {
+        let sources: Vec<VertexId> = graph.vertices().filter(|&(_, v)| v.ins().is_empty()).map(|(id, _)| id).collect();
+        let sinks = graph.vertices().filter(|&(_, v)| v.outs().is_empty()).map(|(id, _)| id).collect();
+
+        let mut reached: BTreeSet<VertexId> = sources.iter().cloned().collect();
+        let mut stack = sources.clone();
+        while let Some(id) = stack.pop() {
+            for &e in graph.vertex(id).unwrap().outs().iter() {
+                let target = graph.edge(e).unwrap().target();
+                if reached.insert(target) {
+                    stack.push(target);
+                }
+            }
+        }
+        let unreachable = graph.vertex_ids().into_iter().filter(|id| !reached.contains(id)).collect();
+
+        Analysis {
+            components: strongly_connected_components(graph),
+            topological_order: topological_order(graph),
+            sources,
+            sinks,
+            unreachable
+        }
+    }
+
+    pub fn is_acyclic(&self) -> bool {
+        self.topological_order.is_some()
+    }
+
+    /// The index into `components` of the component holding `id`.
+    pub fn component_of(&self, id: VertexId) -> Option<usize> {
+        self.components.iter().position(|component| component.contains(&id))
+    }
+
+    /// Whether a component has a cycle in it, so more than one vertex or a self-loop.
+    pub fn is_cyclic(&self, graph: &Graph, component: usize) -> bool {
+        match self.components[component][..] {
+            [id] => graph.find_edge(id, id).is_some(),
+            _ => true
+        }
+    }
+
+    /// A few lines summing up the analysis, naming vertices by label.
+    pub fn report(&self, graph: &Graph) -> String {
+        let names = |ids: &[VertexId]| -> String {
+            if ids.is_empty() {
+                return "none".to_string();
+            }
+            let labels: Vec<&str> = ids.iter().map(|&id| graph.vertex(id).unwrap().label.as_str()).collect();
+            labels.join(", ")
+        };
+
+        let cyclic: Vec<usize> = (0..self.components.len()).filter(|&c| self.is_cyclic(graph, c)).collect();
+        let mut report = String::new();
+        if self.is_acyclic() {
+            report.push_str("Acyclic\n");
+        } else {
+            report.push_str(&format!("Has cycles, in {} of {} components:\n",
+                                     cyclic.len(), self.components.len()));
+            for &c in cyclic.iter() {
+                report.push_str(&format!("  {{{}}}\n", names(&self.components[c])));
+            }
+        }
+        match self.topological_order {
+            Some(ref order) => report.push_str(&format!("Topological order: {}\n", names(order))),
+            None => report.push_str("Topological order: none\n")
+        }
+        report.push_str(&format!("Sources: {}\n", names(&self.sources)));
+        report.push_str(&format!("Sinks: {}\n", names(&self.sinks)));
+        report.push_str(&format!("Unreachable: {}", names(&self.unreachable)));
+        report
+    }
+}
+
+/// Whether there's a path from `from` to `to`, counting the empty path from a vertex to itself.
+pub fn reaches(graph: &Graph, from: VertexId, to: VertexId) -> bool {
+    let mut seen: BTreeSet<VertexId> = BTreeSet::new();
+    let mut stack = vec![from];
+    while let Some(id) = stack.pop() {
+        if id == to {
+            return true;
+        }
+        if !seen.insert(id) {
+            continue;
+        }
+        if let Some(v) = graph.vertex(id) {
+            stack.extend(v.outs().iter().map(|&e| graph.edge(e).unwrap().target()));
+        }
+    }
+    false
+}
+
+/// Whether an edge from `source` to `target` would close a cycle, self-loops included.
+pub fn creates_cycle(graph: &Graph, source: VertexId, target: VertexId) -> bool {
+    reaches(graph, target, source)
+}
+
+/// The first edge, in creation order, that closes a cycle with the edges created
+/// before it, or `None` if the graph is acyclic.
+pub fn first_cycle_edge(graph: &Graph) -> Option<EdgeId> {
+    if topological_order(graph).is_some() {
+        return None;
+    }
+
+    // Once some of the first edges close a cycle, any more of them do too,
+    // so the shortest run of them that does can be found by bisection.
+    let edges: Vec<(EdgeId, VertexId, VertexId)> = graph.edges().map(|(id, e)| (id, e.source(), e.target())).collect();
+    let (mut acyclic, mut cyclic) = (0, edges.len());
+    while cyclic - acyclic > 1 {
+        let middle = (acyclic + cyclic) / 2;
+        if has_cycle(graph, &edges[..middle]) {
+            cyclic = middle;
+        } else {
+            acyclic = middle;
+        }
+    }
+    Some(edges[cyclic - 1].0)
+}
+
+/// Whether the vertices of `graph` have a cycle when only `edges` connect them.
+fn has_cycle(graph: &Graph, edges: &[(EdgeId, VertexId, VertexId)]) -> bool {
+    let mut in_degree: HashMap<VertexId, usize> = graph.vertex_ids().into_iter().map(|id| (id, 0)).collect();
+    let mut targets: HashMap<VertexId, Vec<VertexId>> = HashMap::new();
+    for &(_, source, target) in edges.iter() {
+        *in_degree.get_mut(&target).unwrap() += 1;
+        targets.entry(source).or_default().push(target);
+    }
+
+    let mut ready: Vec<VertexId> = in_degree.iter().filter(|&(_, &d)| d == 0).map(|(&id, _)| id).collect();
+    let mut ordered = 0;
+    while let Some(id) = ready.pop() {
+        ordered += 1;
+        for target in targets.remove(&id).unwrap_or_default() {
+            let degree = in_degree.get_mut(&target).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.push(target);
+            }
+        }
+    }
+    ordered < in_degree.len()
+}
+
+/// Tarjan's algorithm, without recursion so that long paths can't overflow the stack.
+fn strongly_connected_components(graph: &Graph) -> Vec<Vec<VertexId>> {
+    let ids = graph.vertex_ids();
+    let mut index: HashMap<VertexId, usize> = HashMap::new();
+    let mut lowlink: HashMap<VertexId, usize> = HashMap::new();
+    let mut on_stack: BTreeSet<VertexId> = BTreeSet::new();
+    let mut stack: Vec<VertexId> = vec![];
+    let mut components = vec![];
+
+    for &root in ids.iter() {
+        if index.contains_key(&root) {
+            continue;
+        }
+
+        // (vertex, index of the next out-edge to follow)
+        let mut work = vec![(root, 0)];
+        while let Some(&mut (v, ref mut next)) = work.last_mut() {
+            if *next == 0 && !index.contains_key(&v) {
+                let n = index.len();
+                index.insert(v, n);
+                lowlink.insert(v, n);
+                stack.push(v);
+                on_stack.insert(v);
+            }
+
+            let outs = graph.vertex(v).unwrap().outs();
+            if *next < outs.len() {
+                let w = graph.edge(outs[*next]).unwrap().target();
+                *next += 1;
+                if !index.contains_key(&w) {
+                    work.push((w, 0));
+                } else if on_stack.contains(&w) {
+                    let low = lowlink[&v].min(index[&w]);
+                    lowlink.insert(v, low);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                let low = lowlink[&parent].min(lowlink[&v]);
+                lowlink.insert(parent, low);
+            }
+            if lowlink[&v] == index[&v] {
+                let mut component = vec![];
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack.remove(&w);
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort();
+                components.push(component);
+            }
+        }
+    }
+
+    // Tarjan's algorithm finishes a component only after every component it leads to.
+    components.reverse();
+    components
+}
+
+fn topological_order(graph: &Graph) -> Option<Vec<VertexId>> {
+    let mut in_degree: HashMap<VertexId, usize> = graph.vertices().map(|(id, v)| (id, v.ins().len())).collect();
+    let mut ready: BTreeSet<VertexId> = in_degree.iter().filter(|&(_, &d)| d == 0).map(|(&id, _)| id).collect();
+    let mut order = vec![];
+
+    while let Some(&id) = ready.iter().next() {
+        ready.remove(&id);
+        order.push(id);
+        for &e in graph.vertex(id).unwrap().outs().iter() {
+            let target = graph.edge(e).unwrap().target();
+            let degree = in_degree.get_mut(&target).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.insert(target);
+            }
+        }
+    }
+
+    if order.len() == graph.vertex_count() {
+        Some(order)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_edge_that_closes_a_cycle() {
+        let mut graph = Graph::new();
+        let ids: Vec<VertexId> = (0..6).map(|i| graph.add_vertex(i.to_string(), [0.0, 0.0])).collect();
+        for pair in ids.windows(2) {
+            graph.add_edge(pair[0], pair[1]);
+        }
+        assert_eq!(first_cycle_edge(&graph), None);
+
+        let first = graph.add_edge(ids[4], ids[2]).unwrap();
+        graph.add_edge(ids[5], ids[0]);
+        graph.add_edge(ids[3], ids[3]);
+        assert_eq!(first_cycle_edge(&graph), Some(first));
+
+        graph.remove_edge(first);
+        let self_loop = graph.find_edge(ids[3], ids[3]).unwrap();
+        graph.remove_edge(graph.find_edge(ids[5], ids[0]).unwrap());
+        assert_eq!(first_cycle_edge(&graph), Some(self_loop));
+    }
+
+    fn graph(labels: &[&str], edges: &[(usize, usize)]) -> (Graph, Vec<VertexId>) {
+        let mut graph = Graph::new();
+        let ids: Vec<VertexId> = labels.iter().map(|l| graph.add_vertex(l.to_string(), [0.0, 0.0])).collect();
+        for &(source, target) in edges.iter() {
+            graph.add_edge(ids[source], ids[target]).unwrap();
+        }
+        (graph, ids)
+    }
+
+    fn assert_edges_go_forwards(graph: &Graph, analysis: &Analysis) {
+        for (_, e) in graph.edges() {
+            assert!(analysis.component_of(e.source()) <= analysis.component_of(e.target()));
+        }
+    }
+
+    #[test]
+    fn acyclic_graphs() {
+        // created out of order, so the order has to come from the edges
+        let (graph, ids) = graph(&["d", "a", "b", "c"], &[(1, 2), (1, 3), (2, 0), (3, 0)]);
+        let (d, a, b, c) = (ids[0], ids[1], ids[2], ids[3]);
+        let analysis = Analysis::new(&graph);
+        assert!(analysis.is_acyclic());
+        assert_eq!(analysis.topological_order, Some(vec![a, b, c, d]));
+        assert_eq!(analysis.components.len(), 4);
+        assert_edges_go_forwards(&graph, &analysis);
+        assert!((0..4).all(|c| !analysis.is_cyclic(&graph, c)));
+        assert_eq!(analysis.sources, vec![a]);
+        assert_eq!(analysis.sinks, vec![d]);
+        assert_eq!(analysis.unreachable, vec![]);
+        assert_eq!(analysis.report(&graph),
+                   "Acyclic\n\
+                    Topological order: a, b, c, d\n\
+                    Sources: a\n\
+                    Sinks: d\n\
+                    Unreachable: none");
+    }
+
+    #[test]
+    fn cyclic_graphs() {
+        let (graph, ids) = graph(&["a", "b", "c", "d", "e", "f", "g", "h"],
+                                 &[(0, 1), (1, 2), (2, 1), (2, 3), (5, 5), (6, 7), (7, 6), (7, 3)]);
+        let analysis = Analysis::new(&graph);
+        assert!(!analysis.is_acyclic());
+        assert_eq!(analysis.topological_order, None);
+
+        let mut components = analysis.components.clone();
+        components.sort();
+        assert_eq!(components, vec![vec![ids[0]], vec![ids[1], ids[2]], vec![ids[3]],
+                                    vec![ids[4]], vec![ids[5]], vec![ids[6], ids[7]]]);
+        assert_edges_go_forwards(&graph, &analysis);
+        let cyclic = |id| analysis.is_cyclic(&graph, analysis.component_of(id).unwrap());
+        assert!(cyclic(ids[1]) && cyclic(ids[5]) && cyclic(ids[6]));
+        assert!(!cyclic(ids[0]) && !cyclic(ids[3]) && !cyclic(ids[4]));
+
+        assert_eq!(analysis.sources, vec![ids[0], ids[4]]);
+        assert_eq!(analysis.sinks, vec![ids[3], ids[4]]);
+        // d can be reached from a, even though h leads there too
+        assert_eq!(analysis.unreachable, vec![ids[5], ids[6], ids[7]]);
+
+        let report = analysis.report(&graph);
+        assert!(report.starts_with("Has cycles, in 3 of 6 components:\n"));
+        for cycle in ["  {b, c}\n", "  {f}\n", "  {g, h}\n"].iter() {
+            assert!(report.contains(cycle), "{}", report);
+        }
+        assert!(report.ends_with("Topological order: none\n\
+                                  Sources: a, e\n\
+                                  Sinks: d, e\n\
+                                  Unreachable: f, g, h"), "{}", report);
+    }
+
+    #[test]
+    fn empty_graphs() {
+        let analysis = Analysis::new(&Graph::new());
+        assert_eq!(analysis.topological_order, Some(vec![]));
+        assert_eq!(analysis.report(&Graph::new()),
+                   "Acyclic\nTopological order: none\nSources: none\nSinks: none\nUnreachable: none");
+    }
+}
@@ -0,0 +1,297 @@
+//! Reading and writing documents on disk.
+//!
+//! A document file looks like this:
+//!
+//! ```text
+//! adele-document 1
+//!
+//! [vertices]
+//! 0 -200 -100 Hello world!
+//! 1 -200 100 Holy smokes!
+//!
+//! [edges]
+//! 1 0 says
+//!
+//! [options]
+//! dag
+//!
+//! [types]
+//! Type text goes here
+//! ```
+//!
+//! Vertices are numbered from 0 in the order they're listed, followed by their
+//! position and label. Edges are a source and target number, followed by the
+//! edge's label if it has one. Labels escape backslashes and line breaks as `\\` and `\n`.
+//! The optional `[options]` section lists settings of the document, one per line:
+//! `dag` keeps the graph free of cycles, so a document with it can't have any.
+//! Everything after the `[types]` line is the TYPE_TEXT pane, verbatim.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use analysis::first_cycle_edge;
+use graph::{EdgeId, Graph, VertexId};
+
+
+const HEADER: &str = "adele-document 1";
+
+pub struct Document {
+    pub graph: Graph,
+    pub types: String,
+    /// DAG mode: edges that would close a cycle are refused.
+    pub dag: bool
+}
+
+#[derive(Debug)]
+pub enum DocumentError {
+    Io(io::Error),
+    // Lines count from 1.
+    Syntax { line: usize, message: String }
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DocumentError::Io(ref e) => write!(f, "{}", e),
+            DocumentError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl From<io::Error> for DocumentError {
+    fn from(e: io::Error) -> Self {
+        DocumentError::Io(e)
+    }
+}
+
+fn syntax_error<S: Into<String>>(line: usize, message: S) -> DocumentError {
+    DocumentError::Syntax { line, message: message.into() }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str, line: usize) -> Result<String, DocumentError> {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            _ => return Err(syntax_error(line, "bad escape in label"))
+        }
+    }
+    Ok(result)
+}
+
+enum Section {
+    None,
+    Vertices,
+    Edges,
+    Options
+}
+
+impl Document {
+    pub fn new(graph: Graph, types: String) -> Self {
+        Document { graph, types, dag: false }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Document, DocumentError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Document::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), DocumentError> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Document, DocumentError> {
+        let mut graph = Graph::new();
+        let mut ids: Vec<VertexId> = vec![];
+        let mut edge_lines: Vec<(EdgeId, usize)> = vec![];
+        let mut section = Section::None;
+        let mut seen_header = false;
+        let mut dag = false;
+
+        let mut rest = text;
+        let mut line_number = 0;
+        while !rest.is_empty() {
+            let (line, next) = match rest.find('\n') {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => (rest, "")
+            };
+            rest = next;
+            line_number += 1;
+
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if !seen_header {
+                if line.trim() != HEADER {
+                    return Err(syntax_error(line_number, "not an adele document"));
+                }
+                seen_header = true;
+                continue;
+            }
+
+            match line.trim() {
+                "[vertices]" => { section = Section::Vertices; continue; },
+                "[edges]" => { section = Section::Edges; continue; },
+                "[options]" => { section = Section::Options; continue; },
+                "[types]" => {
+                    check_acyclic(&graph, dag, &edge_lines)?;
+                    return Ok(Document { graph, types: rest.to_string(), dag });
+                },
+                _ => ()
+            }
+
+            match section {
+                Section::None => return Err(syntax_error(line_number, "expected a section header")),
+
+                Section::Vertices => {
+                    let mut fields = line.splitn(4, ' ');
+                    let number = fields.next().and_then(|f| f.parse::<usize>().ok());
+                    let x = fields.next().and_then(|f| f.parse::<f64>().ok());
+                    let y = fields.next().and_then(|f| f.parse::<f64>().ok());
+                    let label = fields.next().unwrap_or("");
+                    match (number, x, y) {
+                        (Some(n), Some(x), Some(y)) if n == ids.len() => {
+                            let label = unescape(label, line_number)?;
+                            ids.push(graph.add_vertex(label, [x, y]));
+                        },
+                        (Some(_), Some(_), Some(_)) =>
+                            return Err(syntax_error(line_number,
+                                                    format!("expected vertex number {}", ids.len()))),
+                        _ => return Err(syntax_error(line_number, "expected `<number> <x> <y> <label>`"))
+                    }
+                },
+
+                Section::Edges => {
+                    let mut fields = line.splitn(3, ' ');
+                    let source = fields.next().and_then(|f| f.parse::<usize>().ok());
+                    let target = fields.next().and_then(|f| f.parse::<usize>().ok());
+                    let label = fields.next().unwrap_or("");
+                    match (source, target) {
+                        (Some(source), Some(target)) => {
+                            if source >= ids.len() || target >= ids.len() {
+                                return Err(syntax_error(line_number, "edge refers to an unknown vertex"));
+                            }
+                            match graph.add_edge(ids[source], ids[target]) {
+                                Some(edge) => {
+                                    graph.edge_mut(edge).unwrap().label = unescape(label, line_number)?;
+                                    edge_lines.push((edge, line_number));
+                                },
+                                None => return Err(syntax_error(line_number, "duplicate edge"))
+                            }
+                        },
+                        _ => return Err(syntax_error(line_number, "expected `<source> <target> [<label>]`"))
+                    }
+                },
+
+                Section::Options => match line.trim() {
+                    "dag" => dag = true,
+                    option => return Err(syntax_error(line_number, format!("unknown option `{}`", option)))
+                }
+            }
+        }
+
+        if !seen_header {
+            return Err(syntax_error(1, "not an adele document"));
+        }
+        check_acyclic(&graph, dag, &edge_lines)?;
+        Ok(Document { graph, types: String::new(), dag })
+    }
+}
+
+/// In DAG mode, fails at the line of the first edge that closes a cycle.
+fn check_acyclic(graph: &Graph, dag: bool, edge_lines: &[(EdgeId, usize)]) -> Result<(), DocumentError> {
+    let edge = match if dag { first_cycle_edge(graph) } else { None } {
+        Some(edge) => edge,
+        None => return Ok(())
+    };
+    let &(_, line) = edge_lines.iter().find(|&&(e, _)| e == edge).unwrap();
+    let e = graph.edge(edge).unwrap();
+    let label = |id| &graph.vertex(id).unwrap().label;
+    Err(syntax_error(line, format!("`{} => {}` closes a cycle", label(e.source()), label(e.target()))))
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut numbers = BTreeMap::new();
+
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f)?;
+        writeln!(f, "[vertices]")?;
+        for (n, (id, v)) in self.graph.vertices().enumerate() {
+            numbers.insert(id, n);
+            writeln!(f, "{} {} {} {}", n, v.position[0], v.position[1], escape(&v.label))?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "[edges]")?;
+        for (_, e) in self.graph.edges() {
+            if e.label.is_empty() {
+                writeln!(f, "{} {}", numbers[&e.source()], numbers[&e.target()])?;
+            } else {
+                writeln!(f, "{} {} {}", numbers[&e.source()], numbers[&e.target()], escape(&e.label))?;
+            }
+        }
+
+        if self.dag {
+            writeln!(f)?;
+            writeln!(f, "[options]")?;
+            writeln!(f, "dag")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "[types]")?;
+        write!(f, "{}", self.types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("two\nlines \\".to_string(), [-200.0, -100.5]);
+        let b = graph.add_vertex("b".to_string(), [0.0, 100.0]);
+        let e = graph.add_edge(b, a).unwrap();
+        graph.edge_mut(e).unwrap().label = "says".to_string();
+        let document = Document { dag: true, ..Document::new(graph.clone(), "type T;\n".to_string()) };
+
+        let parsed = Document::parse(&document.to_string()).unwrap();
+        assert_eq!(parsed.graph, graph);
+        assert_eq!(parsed.types, "type T;\n");
+        assert!(parsed.dag);
+    }
+
+    #[test]
+    fn cycles_in_dag_documents() {
+        let cyclic = "adele-document 1\n\n[vertices]\n0 0 0 a\n1 0 0 b\n\n[edges]\n0 1\n1 0\n";
+        assert!(Document::parse(cyclic).is_ok());
+
+        match Document::parse(&format!("{}\n[options]\ndag\n", cyclic)) {
+            Err(DocumentError::Syntax { line, message }) => {
+                assert_eq!(line, 9);
+                assert_eq!(message, "`b => a` closes a cycle");
+            },
+            _ => panic!("a cycle in a DAG document should be an error")
+        }
+    }
+}
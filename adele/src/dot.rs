@@ -0,0 +1,673 @@
+//! Graphviz DOT export and import.
+//!
+//! Export writes a `digraph` with one node per vertex, named `n0`, `n1`, ...
+//! in creation order, with the label and optionally the position as attributes.
+//! Edges with a label get it as their `label` attribute.
+//!
+//! Import reads the whole DOT language: node, edge and attribute statements,
+//! attribute lists, ports, subgraphs (also as edge endpoints) and comments.
+//! Only what the graph model can hold is kept: a node's `label` (falling back
+//! to its ID) and `pos` become the vertex label and position, and every edge
+//! becomes an edge from its left to its right end, also in undirected graphs,
+//! labelled by its `label` attribute.
+//! Repeated edges collapse into one. Node defaults set with `node [...]` apply
+//! within the subgraph they appear in.
+//!
+//! In labels, `\\` stands for a backslash and `\n`, `\l` and `\r` for a line
+//! break, both ways. On import, `\N` in a node label stands for the node ID and
+//! `\G` in any label for the graph's name, so `node [label="\N"]` as Graphviz
+//! writes it labels every vertex by its ID. Other escapes are kept as they are.
+
+use std::collections::HashMap;
+
+use graph::{Graph, VertexId};
+use lexer::{Cursor, Pos};
+use text::ParseError;
+
+
+fn quote(s: &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes the graph in DOT. With `positions` set, every node gets a `pos`
+/// attribute in the same coordinates as `Vertex::position`.
+pub fn graph_to_dot(graph: &Graph, positions: bool) -> String {
+    let mut names = HashMap::new();
+    let mut dot = "digraph {\n".to_string();
+
+    for (n, (id, v)) in graph.vertices().enumerate() {
+        let name = format!("n{}", n);
+        dot.push_str(&format!("    {} [label={}", name, quote(&v.label)));
+        if positions {
+            dot.push_str(&format!(", pos=\"{},{}\"", v.position[0], v.position[1]));
+        }
+        dot.push_str("];\n");
+        names.insert(id, name);
+    }
+
+    for (_, e) in graph.edges() {
+        if e.label.is_empty() {
+            dot.push_str(&format!("    {} -> {};\n", names[&e.source()], names[&e.target()]));
+        } else {
+            dot.push_str(&format!("    {} -> {} [label={}];\n", names[&e.source()], names[&e.target()], quote(&e.label)));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    // Any kind of ID: names, numerals, quoted and HTML strings. Keywords are IDs too.
+    Id(String),
+    // An ID that was quoted, so that `"graph"` isn't taken for a keyword.
+    Quoted(String),
+    Arrow,
+    Line,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Equals,
+    Semicolon,
+    Comma,
+    Colon
+}
+
+struct Lexer {
+    cursor: Cursor
+}
+
+impl Lexer {
+    fn new(text: &str) -> Self {
+        Lexer { cursor: Cursor::new(text) }
+    }
+
+    fn skip_space_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.cursor.peek(0), self.cursor.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => { self.cursor.bump(); },
+                (Some('/'), Some('/')) => self.skip_line(),
+                // `#` lines are C preprocessor output
+                (Some('#'), _) if self.cursor.pos.column == 1 => self.skip_line(),
+                (Some('/'), Some('*')) => {
+                    let start = self.cursor.pos;
+                    self.cursor.bump();
+                    self.cursor.bump();
+                    loop {
+                        match (self.cursor.peek(0), self.cursor.peek(1)) {
+                            (Some('*'), Some('/')) => { self.cursor.bump(); self.cursor.bump(); break; },
+                            (Some(_), _) => { self.cursor.bump(); },
+                            (None, _) => return Err(start.error("unterminated comment"))
+                        }
+                    }
+                },
+                _ => return Ok(())
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.cursor.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, Pos)>, ParseError> {
+        self.skip_space_and_comments()?;
+        let start = self.cursor.pos;
+
+        let c = match self.cursor.peek(0) {
+            Some(c) => c,
+            None => return Ok(None)
+        };
+
+        let single = match c {
+            '{' => Some(Token::LeftBrace),
+            '}' => Some(Token::RightBrace),
+            '[' => Some(Token::LeftBracket),
+            ']' => Some(Token::RightBracket),
+            '=' => Some(Token::Equals),
+            ';' => Some(Token::Semicolon),
+            ',' => Some(Token::Comma),
+            ':' => Some(Token::Colon),
+            _ => None
+        };
+        if let Some(token) = single {
+            self.cursor.bump();
+            return Ok(Some((token, start)));
+        }
+
+        match (c, self.cursor.peek(1)) {
+            ('-', Some('>')) => {
+                self.cursor.bump();
+                self.cursor.bump();
+                Ok(Some((Token::Arrow, start)))
+            },
+            ('-', Some('-')) => {
+                self.cursor.bump();
+                self.cursor.bump();
+                Ok(Some((Token::Line, start)))
+            },
+            ('"', _) => self.quoted(start).map(|s| Some((Token::Quoted(s), start))),
+            ('<', _) => self.html(start).map(|s| Some((Token::Quoted(s), start))),
+            (c, _) if c == '-' || c == '.' || c.is_ascii_digit() => {
+                let mut numeral = String::new();
+                while let Some(c) = self.cursor.peek(0) {
+                    if !(c == '.' || c.is_ascii_digit() || (c == '-' && numeral.is_empty())) {
+                        break;
+                    }
+                    numeral.push(c);
+                    self.cursor.bump();
+                }
+                if numeral == "-" || numeral == "." || numeral == "-." {
+                    return Err(start.error("expected a number"));
+                }
+                Ok(Some((Token::Id(numeral), start)))
+            },
+            (c, _) if c == '_' || c.is_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = self.cursor.peek(0) {
+                    if !(c == '_' || c.is_alphanumeric()) {
+                        break;
+                    }
+                    name.push(c);
+                    self.cursor.bump();
+                }
+                Ok(Some((Token::Id(name), start)))
+            },
+            (c, _) => Err(start.error(format!("unexpected `{}`", c)))
+        }
+    }
+
+    /// Reads a double quoted string, and any strings joined onto it with `+`.
+    fn quoted(&mut self, start: Pos) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            self.cursor.bump(); // opening quote
+            loop {
+                match self.cursor.bump() {
+                    None => return Err(start.error("unterminated string")),
+                    Some('"') => break,
+                    Some('\\') if self.cursor.peek(0) == Some('"') => {
+                        self.cursor.bump();
+                        s.push('"');
+                    },
+                    Some('\\') if self.cursor.peek(0) == Some('\n') => {
+                        // line continuation
+                        self.cursor.bump();
+                    },
+                    // left for `label_text` to decode, as long as the second one doesn't escape a quote
+                    Some('\\') if self.cursor.peek(0) == Some('\\') => {
+                        self.cursor.bump();
+                        s.push_str("\\\\");
+                    },
+                    Some(c) => s.push(c)
+                }
+            }
+
+            // look past whitespace for a `+ "..."`
+            let saved = self.cursor.save();
+            self.skip_space_and_comments()?;
+            if self.cursor.peek(0) == Some('+') {
+                self.cursor.bump();
+                self.skip_space_and_comments()?;
+                if self.cursor.peek(0) == Some('"') {
+                    continue;
+                }
+                return Err(self.cursor.pos.error("expected a string after `+`"));
+            }
+            self.cursor.restore(saved);
+            return Ok(s);
+        }
+    }
+
+    /// Reads an HTML string, which is delimited by balanced angle brackets.
+    fn html(&mut self, start: Pos) -> Result<String, ParseError> {
+        self.cursor.bump();
+        let mut depth = 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.cursor.bump() {
+                Some(c) => c,
+                None => return Err(start.error("unterminated HTML string"))
+            };
+            match c {
+                '<' => depth += 1,
+                '>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(s);
+                    }
+                },
+                _ => ()
+            }
+            s.push(c);
+        }
+    }
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    match *token {
+        Token::Id(ref id) => id.eq_ignore_ascii_case(keyword),
+        _ => false
+    }
+}
+
+type Attributes = Vec<(String, String)>;
+
+struct Parser {
+    lexer: Lexer,
+    peeked: Option<Option<(Token, Pos)>>,
+    graph: Graph,
+    // The ID after `graph` or `digraph`, if there is one.
+    name: String,
+    nodes: HashMap<String, VertexId>,
+    // Node attribute defaults, one map per open subgraph.
+    node_defaults: Vec<HashMap<String, String>>,
+    // Vertices mentioned in the innermost open subgraph, for edges to or from it.
+    mentioned: Vec<VertexId>
+}
+
+impl Parser {
+    fn peek(&mut self) -> Result<Option<&(Token, Pos)>, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next()?);
+        }
+        Ok(self.peeked.as_ref().unwrap().as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<(Token, Pos)>, ParseError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next()
+        }
+    }
+
+    fn end_pos(&self) -> Pos {
+        self.lexer.cursor.pos
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<Pos, ParseError> {
+        match self.next()? {
+            Some((ref token, pos)) if *token == expected => Ok(pos),
+            Some((_, pos)) => Err(pos.error(format!("expected {}", what))),
+            None => Err(self.end_pos().error(format!("expected {}", what)))
+        }
+    }
+
+    fn expect_id(&mut self) -> Result<(String, Pos), ParseError> {
+        match self.next()? {
+            Some((Token::Id(id), pos)) | Some((Token::Quoted(id), pos)) => Ok((id, pos)),
+            Some((_, pos)) => Err(pos.error("expected an ID")),
+            None => Err(self.end_pos().error("expected an ID"))
+        }
+    }
+
+    fn peek_is(&mut self, token: Token) -> Result<bool, ParseError> {
+        Ok(self.peek()?.is_some_and(|(t, _)| *t == token))
+    }
+
+    fn peek_keyword(&mut self, keyword: &str) -> Result<bool, ParseError> {
+        Ok(self.peek()?.is_some_and(|(t, _)| is_keyword(t, keyword)))
+    }
+
+    fn peek_id(&mut self) -> Result<bool, ParseError> {
+        Ok(matches!(self.peek()?, Some(&(Token::Id(_), _)) | Some(&(Token::Quoted(_), _))))
+    }
+
+    fn graph(&mut self) -> Result<(), ParseError> {
+        if self.peek_keyword("strict")? {
+            self.next()?;
+        }
+        match self.next()? {
+            Some((ref t, _)) if is_keyword(t, "graph") || is_keyword(t, "digraph") => (),
+            Some((_, pos)) => return Err(pos.error("expected `graph` or `digraph`")),
+            None => return Err(self.end_pos().error("expected `graph` or `digraph`"))
+        }
+        if self.peek_id()? {
+            self.name = self.expect_id()?.0;
+        }
+        self.expect(Token::LeftBrace, "`{`")?;
+        self.statements()?;
+        self.expect(Token::RightBrace, "`}`")?;
+
+        if let Some(&(_, pos)) = self.peek()? {
+            return Err(pos.error("expected the end of the file"));
+        }
+        Ok(())
+    }
+
+    /// Reads statements up to, but not including, the closing brace.
+    fn statements(&mut self) -> Result<(), ParseError> {
+        while !self.peek_is(Token::RightBrace)? {
+            if self.peek()?.is_none() {
+                return Err(self.end_pos().error("expected `}`"));
+            }
+            self.statement()?;
+            if self.peek_is(Token::Semicolon)? {
+                self.next()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), ParseError> {
+        if self.peek_keyword("graph")? || self.peek_keyword("edge")? {
+            self.next()?;
+            self.attribute_lists()?;
+            return Ok(());
+        }
+        if self.peek_keyword("node")? {
+            self.next()?;
+            let attributes = self.attribute_lists()?;
+            let defaults = self.node_defaults.last_mut().unwrap();
+            for (key, value) in attributes {
+                defaults.insert(key, value);
+            }
+            return Ok(());
+        }
+
+        let left = if self.peek_keyword("subgraph")? || self.peek_is(Token::LeftBrace)? {
+            self.subgraph()?
+        } else {
+            let (id, _) = self.node_id()?;
+
+            // `ID = ID` sets a graph attribute
+            if self.peek_is(Token::Equals)? {
+                self.next()?;
+                self.expect_id()?;
+                return Ok(());
+            }
+
+            if !self.peek_is(Token::Arrow)? && !self.peek_is(Token::Line)? {
+                let attributes = self.attribute_lists()?;
+                let vertex = self.node(&id);
+                self.apply_node_attributes(vertex, &id, &attributes);
+                return Ok(());
+            }
+            vec![self.node(&id)]
+        };
+
+        let mut ends = vec![left];
+        while self.peek_is(Token::Arrow)? || self.peek_is(Token::Line)? {
+            self.next()?;
+            let right = if self.peek_keyword("subgraph")? || self.peek_is(Token::LeftBrace)? {
+                self.subgraph()?
+            } else {
+                let (id, _) = self.node_id()?;
+                vec![self.node(&id)]
+            };
+            ends.push(right);
+        }
+        if ends.len() == 1 {
+            // a lone subgraph
+            return Ok(());
+        }
+
+        let attributes = self.attribute_lists()?;
+        let label = attributes.iter().rev().find(|(key, _)| key == "label").map(|(_, value)| label_text(value, None, &self.name));
+        for pair in ends.windows(2) {
+            for &source in pair[0].iter() {
+                for &target in pair[1].iter() {
+                    // a repeated edge still takes the label of the last statement that has one
+                    let edge = self.graph.add_edge(source, target)
+                        .or_else(|| self.graph.find_edge(source, target))
+                        .unwrap();
+                    if let Some(ref label) = label {
+                        self.graph.edge_mut(edge).unwrap().label = label.clone();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a node ID, skipping any port after it.
+    fn node_id(&mut self) -> Result<(String, Pos), ParseError> {
+        let id = self.expect_id()?;
+        for _ in 0..2 {
+            if !self.peek_is(Token::Colon)? {
+                break;
+            }
+            self.next()?;
+            self.expect_id()?;
+        }
+        Ok(id)
+    }
+
+    /// Reads a subgraph and returns the vertices mentioned in it.
+    fn subgraph(&mut self) -> Result<Vec<VertexId>, ParseError> {
+        if self.peek_keyword("subgraph")? {
+            self.next()?;
+            if self.peek_id()? {
+                self.next()?;
+            }
+        }
+        self.expect(Token::LeftBrace, "`{`")?;
+
+        let inherited = self.node_defaults.last().unwrap().clone();
+        self.node_defaults.push(inherited);
+        let mentioned_before = ::std::mem::take(&mut self.mentioned);
+
+        self.statements()?;
+        self.expect(Token::RightBrace, "`}`")?;
+
+        self.node_defaults.pop();
+        let mentioned = ::std::mem::replace(&mut self.mentioned, mentioned_before);
+        // the enclosing graph mentions everything its subgraphs do
+        for &v in mentioned.iter() {
+            if !self.mentioned.contains(&v) {
+                self.mentioned.push(v);
+            }
+        }
+        Ok(mentioned)
+    }
+
+    fn attribute_lists(&mut self) -> Result<Attributes, ParseError> {
+        let mut attributes = vec![];
+        while self.peek_is(Token::LeftBracket)? {
+            self.next()?;
+            while !self.peek_is(Token::RightBracket)? {
+                let (key, _) = self.expect_id()?;
+                self.expect(Token::Equals, "`=`")?;
+                let (value, _) = self.expect_id()?;
+                attributes.push((key, value));
+                if self.peek_is(Token::Comma)? || self.peek_is(Token::Semicolon)? {
+                    self.next()?;
+                }
+            }
+            self.next()?;
+        }
+        Ok(attributes)
+    }
+
+    /// Finds or creates the vertex for a node ID.
+    fn node(&mut self, id: &str) -> VertexId {
+        let vertex = match self.nodes.get(id) {
+            Some(&vertex) => vertex,
+            None => {
+                let vertex = self.graph.add_vertex(id.to_string(), [0.0, 0.0]);
+                self.nodes.insert(id.to_string(), vertex);
+                let defaults: Attributes = self.node_defaults.last().unwrap()
+                    .iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                self.apply_node_attributes(vertex, id, &defaults);
+                vertex
+            }
+        };
+        if !self.mentioned.contains(&vertex) {
+            self.mentioned.push(vertex);
+        }
+        vertex
+    }
+
+    fn apply_node_attributes(&mut self, vertex: VertexId, id: &str, attributes: &[(String, String)]) {
+        let v = self.graph.vertex_mut(vertex).unwrap();
+        for (key, value) in attributes {
+            match key.as_str() {
+                "label" => v.label = label_text(value, Some(id), &self.name),
+                "pos" => {
+                    if let Some(position) = parse_pos(value) {
+                        v.position = position;
+                    }
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+/// Decodes the escapes in a label, with `node` the ID of the node it labels.
+fn label_text(value: &str, node: Option<&str>, graph: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match (chars.next(), node) {
+            (Some('\\'), _) => text.push('\\'),
+            // left justified, centered and right justified line breaks are all just line breaks
+            (Some('n'), _) | (Some('l'), _) | (Some('r'), _) => text.push('\n'),
+            (Some('N'), Some(node)) => text.push_str(node),
+            (Some('G'), _) => text.push_str(graph),
+            (Some(c), _) => {
+                text.push('\\');
+                text.push(c);
+            },
+            (None, _) => text.push('\\')
+        }
+    }
+    text
+}
+
+/// Reads a node position like `"12.5,-3"` or `"12.5,-3!"`.
+fn parse_pos(value: &str) -> Option<[f64; 2]> {
+    let value = value.trim().trim_end_matches('!');
+    let mut parts = value.split(',');
+    let x = parts.next()?.trim().parse().ok()?;
+    let y = parts.next()?.trim().parse().ok()?;
+    Some([x, y])
+}
+
+/// Reads a DOT file into a graph, as described at the top of this module.
+/// Nodes without a `pos` attribute start at the origin.
+pub fn parse_dot(text: &str) -> Result<Graph, ParseError> {
+    let mut parser = Parser {
+        lexer: Lexer::new(text),
+        peeked: None,
+        graph: Graph::new(),
+        name: String::new(),
+        nodes: HashMap::new(),
+        node_defaults: vec![HashMap::new()],
+        mentioned: vec![]
+    };
+    parser.graph()?;
+    Ok(parser.graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(graph: &Graph) -> Vec<String> {
+        graph.vertices().map(|(_, v)| v.label.clone()).collect()
+    }
+
+    fn edges(graph: &Graph) -> Vec<(String, String, String)> {
+        let label = |id| graph.vertex(id).unwrap().label.clone();
+        graph.edges().map(|(_, e)| (label(e.source()), label(e.target()), e.label.clone())).collect()
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("say \"hi\"\nback\\slash\\".to_string(), [-1.5, 2.0]);
+        let b = graph.add_vertex("b".to_string(), [3.0, 4.0]);
+        graph.add_edge(a, b);
+        let e = graph.add_edge(b, a).unwrap();
+        graph.edge_mut(e).unwrap().label = "back".to_string();
+        graph.add_edge(b, b);
+
+        let parsed = parse_dot(&graph_to_dot(&graph, true)).unwrap();
+        assert_eq!(labels(&parsed), labels(&graph));
+        assert_eq!(edges(&parsed), edges(&graph));
+        let positions: Vec<[f64; 2]> = parsed.vertices().map(|(_, v)| v.position).collect();
+        assert_eq!(positions, vec![[-1.5, 2.0], [3.0, 4.0]]);
+    }
+
+    #[test]
+    fn statements_and_attributes() {
+        let graph = parse_dot(r#"
+            strict digraph G {
+                // comments are skipped
+                rankdir = LR; /* so are these */
+                a [label="Alpha", shape=box];
+                a -> b -> c [label=next];
+                b:port:n -> a;
+                "quoted id" -> a
+            }
+        "#).unwrap();
+        assert_eq!(labels(&graph), vec!["Alpha", "b", "c", "quoted id"]);
+        assert_eq!(edges(&graph), vec![
+            ("Alpha".to_string(), "b".to_string(), "next".to_string()),
+            ("b".to_string(), "c".to_string(), "next".to_string()),
+            ("b".to_string(), "Alpha".to_string(), String::new()),
+            ("quoted id".to_string(), "Alpha".to_string(), String::new())
+        ]);
+    }
+
+    #[test]
+    fn subgraphs() {
+        let graph = parse_dot("graph { node [label=x]; a -- { b c }; subgraph s { node [label=y]; d } e }").unwrap();
+        assert_eq!(labels(&graph), vec!["x", "x", "x", "y", "x"]);
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[test]
+    fn escapes() {
+        let graph = parse_dot(r#"digraph { a [label="left\lcenter\nright\r\\ \N \"q\" \x"] }"#).unwrap();
+        assert_eq!(labels(&graph), vec!["left\ncenter\nright\n\\ a \"q\" \\x"]);
+    }
+
+    #[test]
+    fn names_in_labels() {
+        // the start of what `dot -Tdot` writes
+        let graph = parse_dot(r#"digraph G {
+            node [label="\N"];
+            a -> b [label="in \G"];
+            c [label="\\N of \G"];
+            "d\\e"
+        }"#).unwrap();
+        assert_eq!(labels(&graph), vec!["a", "b", "\\N of G", "d\\\\e"]);
+        assert_eq!(edges(&graph)[0].2, "in G");
+    }
+
+    #[test]
+    fn repeated_edges_collapse() {
+        let graph = parse_dot("digraph { a -> b; a -> b [label=again] }").unwrap();
+        assert_eq!(edges(&graph), vec![("a".to_string(), "b".to_string(), "again".to_string())]);
+    }
+
+    #[test]
+    fn errors() {
+        let e = parse_dot("digraph {\n  a -> ;\n}").unwrap_err();
+        assert_eq!((e.line, e.column, e.message), (2, 8, "expected an ID".to_string()));
+        assert!(parse_dot("digraph { a [label=\"open] }").is_err());
+        assert!(parse_dot("tree { }").is_err());
+    }
+}
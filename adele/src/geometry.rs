@@ -0,0 +1,374 @@
+//! Shapes shared by everything that draws a graph, so that the window and
+//! exported pictures agree on them.
+
+use graph::{EdgeId, Graph};
+
+
+pub type Point = [f64; 2];
+
+// How many straight pieces a self-loop or a curved edge is drawn with.
+const LOOP_SEGMENTS: usize = 16;
+const CURVE_SEGMENTS: usize = 24;
+
+/// An edge as it's drawn: a line through the points of `path`,
+/// ending at the base of a filled triangle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arrow {
+    pub path: Vec<Point>,
+    /// The tip of the triangle comes first.
+    pub head: [Point; 3]
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    let dx = b[0] - a[0];
+    let dy = b[1] - a[1];
+    (dx*dx + dy*dy).sqrt()
+}
+
+fn distance_to_segment(p: Point, a: Point, b: Point) -> f64 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let ap = [p[0] - a[0], p[1] - a[1]];
+    let length_squared = ab[0]*ab[0] + ab[1]*ab[1];
+    if length_squared == 0.0 {
+        return distance(p, a);
+    }
+    let t = ((ap[0]*ab[0] + ap[1]*ab[1]) / length_squared).clamp(0.0, 1.0);
+    distance(p, [a[0] + ab[0] * t, a[1] + ab[1] * t])
+}
+
+impl Arrow {
+    /// The same arrow with every point passed through `f`, which should keep
+    /// angles intact, like moving and scaling onto the screen does.
+    pub fn map<F: Fn(Point) -> Point>(&self, f: F) -> Arrow {
+        Arrow {
+            path: self.path.iter().map(|&p| f(p)).collect(),
+            head: [f(self.head[0]), f(self.head[1]), f(self.head[2])]
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.path.iter().chain(self.head.iter()).all(|p| p[0].is_finite() && p[1].is_finite())
+    }
+
+    /// Where an edge label goes: `distance` away from halfway along the path,
+    /// on the left when looking along the arrow.
+    pub fn label_position(&self, distance_from_line: f64) -> Point {
+        let total: f64 = self.path.windows(2).map(|w| distance(w[0], w[1])).sum();
+        let mut left = total / 2.0;
+        for w in self.path.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let length = distance(a, b);
+            if length == 0.0 {
+                continue;
+            }
+            if left <= length {
+                let t = left / length;
+                let middle = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+                let dx = (b[0] - a[0]) / length;
+                let dy = (b[1] - a[1]) / length;
+                // rotate left: (x, y) => (-y, x)
+                return [middle[0] - dy * distance_from_line, middle[1] + dx * distance_from_line];
+            }
+            left -= length;
+        }
+        self.path.first().cloned().unwrap_or(self.head[0])
+    }
+
+    /// How far `p` is from the line, including the bit the head covers.
+    pub fn distance_to(&self, p: Point) -> f64 {
+        let last = *self.path.last().unwrap_or(&self.head[0]);
+        self.path.windows(2)
+            .map(|w| distance_to_segment(p, w[0], w[1]))
+            .fold(distance_to_segment(p, last, self.head[0]), f64::min)
+    }
+}
+
+/// A triangle whose base is centered on `at`, pointing along the unit vector `direction`.
+fn head(at: Point, direction: Point, base: f64, height: f64) -> [Point; 3] {
+    // arrow at the end of the line.
+    //         _      _
+    //        /0\     |
+    //       /   \    | h
+    //      /1   2\   |
+    //      -------   -
+    //         b
+    //
+    // We can use the normalized vector from before to build up
+    // the triangle. Keep in mind how 90 degree rotations work
+    // in 2D space:
+    //
+    // rotate left: (x, y) => (-y, x)
+    // rotate right: (x, y) => (y, -x)
+    // rotate 180: (x, y) => (-y, -x)
+
+    let h_vector = [direction[0] * height, direction[1] * height];
+    let b_vector = [direction[0] * base / 2.0, direction[1] * base / 2.0];
+
+    [
+        [at[0] + h_vector[0], at[1] + h_vector[1]],
+        [at[0] - b_vector[1], at[1] + b_vector[0]],
+        [at[0] + b_vector[1], at[1] - b_vector[0]]
+    ]
+}
+
+/// An arrow from `start` towards `end` that stops `subtract` short of it,
+/// e.g. at the outline of the target vertex.
+pub fn arrow(start: Point, end: Point, subtract: f64, base: f64, height: f64) -> Arrow {
+    let dx = end[0] - start[0];
+    let dy = end[1] - start[1];
+    let dist = (dx*dx + dy*dy).sqrt();
+    let norm_dx = dx / dist;
+    let norm_dy = dy / dist;
+    let new_dist = dist - subtract - height;
+    let new_dx = norm_dx * new_dist;
+    let new_dy = norm_dy * new_dist;
+
+    let new_to = [start[0] + new_dx, start[1] + new_dy];
+
+    Arrow {
+        path: vec![start, new_to],
+        head: head(new_to, [norm_dx, norm_dy], base, height)
+    }
+}
+
+/// An arrow from `start` to `end` that bows out `bend` to its left halfway,
+/// stopping `subtract` short of `end` like `arrow`.
+pub fn curved_arrow(start: Point, end: Point, subtract: f64, bend: f64, base: f64, height: f64) -> Arrow {
+    let dx = end[0] - start[0];
+    let dy = end[1] - start[1];
+    let dist = (dx*dx + dy*dy).sqrt();
+    if dist <= subtract + height {
+        // no room for a curve
+        return arrow(start, end, subtract, base, height);
+    }
+
+    // A quadratic Bézier curve reaches halfway to its control point, so the
+    // control point goes twice as far out as the bend.
+    let control = [(start[0] + end[0]) / 2.0 - dy / dist * 2.0 * bend,
+                   (start[1] + end[1]) / 2.0 + dx / dist * 2.0 * bend];
+    let curve: Vec<Point> = (0..CURVE_SEGMENTS + 1).map(|i| {
+        let t = i as f64 / CURVE_SEGMENTS as f64;
+        let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * (1.0 - t) * t, t * t);
+        [a * start[0] + b * control[0] + c * end[0],
+         a * start[1] + b * control[1] + c * end[1]]
+    }).collect();
+
+    // Where the curve gets within `within` of the end, as the index of the last
+    // point before that and the crossing itself.
+    let crossing = |within: f64| {
+        let i = (0..curve.len()).rev().find(|&i| distance(curve[i], end) >= within).unwrap_or(0);
+        if i + 1 == curve.len() {
+            return (i, curve[i]);
+        }
+        let (outer, inner) = (distance(curve[i], end), distance(curve[i + 1], end));
+        let t = if outer > inner { (outer - within) / (outer - inner) } else { 0.0 };
+        (i, [curve[i][0] + (curve[i + 1][0] - curve[i][0]) * t,
+             curve[i][1] + (curve[i + 1][1] - curve[i][1]) * t])
+    };
+
+    let (last, base_center) = crossing(subtract + height);
+    let (_, tip) = crossing(subtract);
+    let mut path = curve[..last + 1].to_vec();
+    path.push(base_center);
+
+    let length = distance(base_center, tip);
+    let direction = if length > 0.0 {
+        [(tip[0] - base_center[0]) / length, (tip[1] - base_center[1]) / length]
+    } else {
+        [dx / dist, dy / dist]
+    };
+    Arrow { path, head: head(base_center, direction, base, length) }
+}
+
+/// An arrow from the outline of the vertex at `center` back to it, looping over its top.
+pub fn loop_arrow(center: Point, radius: f64, base: f64, height: f64) -> Arrow {
+    // The loop is part of a circle centered on the top of the vertex.
+    let loop_radius = radius / 2.0;
+    let loop_center = [center[0], center[1] + radius];
+
+    // Where the two circles cross, relative to the loop's center: both are
+    // `a` above the vertex's center and `h` to either side.
+    let a = radius - loop_radius * loop_radius / (2.0 * radius);
+    let h = (radius * radius - a * a).sqrt();
+    let start = (a - radius).atan2(-h) + 2.0 * ::std::f64::consts::PI;
+    let end = (a - radius).atan2(h);
+
+    // Go clockwise from the left crossing over the top, stopping short of
+    // the right crossing to leave room for the head.
+    let stop = end + (height / loop_radius).min(start - end);
+    let on_loop = |angle: f64| [loop_center[0] + loop_radius * angle.cos(),
+                                loop_center[1] + loop_radius * angle.sin()];
+    let path: Vec<Point> = (0..LOOP_SEGMENTS + 1)
+        .map(|i| on_loop(start + (stop - start) * i as f64 / LOOP_SEGMENTS as f64))
+        .collect();
+
+    let base_center = on_loop(stop);
+    let tip = on_loop(end);
+    let length = distance(base_center, tip);
+    let direction = if length > 0.0 {
+        [(tip[0] - base_center[0]) / length, (tip[1] - base_center[1]) / length]
+    } else {
+        [0.0, -1.0]
+    };
+
+    Arrow { path, head: head(base_center, direction, base, length) }
+}
+
+/// The arrow an edge is drawn as, between vertices of the given radius,
+/// or `None` if there's no such edge.
+///
+/// When there are edges both ways between two vertices, each one curves off
+/// to its own left so they don't cover each other.
+pub fn edge_arrow(graph: &Graph, id: EdgeId, radius: f64, base: f64, height: f64) -> Option<Arrow> {
+    let edge = graph.edge(id)?;
+    let start = graph.vertex(edge.source())?.position;
+    let end = graph.vertex(edge.target())?.position;
+    if edge.source() == edge.target() {
+        Some(loop_arrow(start, radius, base, height))
+    } else if graph.find_edge(edge.target(), edge.source()).is_some() {
+        Some(curved_arrow(start, end, radius, radius / 2.0, base, height))
+    } else {
+        Some(arrow(start, end, radius, base, height))
+    }
+}
+
+/// How graph coordinates map onto the screen: scaled by `zoom`, then moved by `pan`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub pan: Point,
+    pub zoom: f64
+}
+
+impl View {
+    pub fn new() -> Self {
+        View { pan: [0.0, 0.0], zoom: 1.0 }
+    }
+
+    pub fn to_screen(&self, p: Point) -> Point {
+        [p[0] * self.zoom + self.pan[0], p[1] * self.zoom + self.pan[1]]
+    }
+
+    pub fn to_graph(&self, p: Point) -> Point {
+        [(p[0] - self.pan[0]) / self.zoom, (p[1] - self.pan[1]) / self.zoom]
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the graph point at `screen` in place.
+    pub fn zoom_around(&mut self, screen: Point, factor: f64) {
+        let fixed = self.to_graph(screen);
+        self.zoom *= factor;
+        self.pan = [screen[0] - fixed[0] * self.zoom, screen[1] - fixed[1] * self.zoom];
+    }
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Point, b: Point) {
+        assert!(distance(a, b) < 1e-9, "{:?} isn't {:?}", a, b);
+    }
+
+    #[test]
+    fn arrows_stop_at_the_outline() {
+        let straight = arrow([0.0, 0.0], [100.0, 0.0], 10.0, 15.0, 8.0);
+        assert_eq!(straight.path, vec![[0.0, 0.0], [82.0, 0.0]]);
+        assert_close(straight.head[0], [90.0, 0.0]);
+        assert_close(straight.head[1], [82.0, 7.5]);
+        assert_close(straight.head[2], [82.0, -7.5]);
+
+        // and at an angle
+        let (start, end) = ([1.0, 2.0], [31.0, 42.0]);
+        let slanted = arrow(start, end, 10.0, 15.0, 8.0);
+        assert!((distance(slanted.head[0], end) - 10.0).abs() < 1e-9);
+        assert!((distance(slanted.path[1], end) - 18.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn loops_start_and_end_on_the_outline() {
+        let (center, radius) = ([10.0, -20.0], 30.0);
+        let arrow = loop_arrow(center, radius, 12.0, 8.0);
+        assert!(arrow.is_finite());
+        assert!((distance(arrow.path[0], center) - radius).abs() < 1e-9);
+        assert!((distance(arrow.head[0], center) - radius).abs() < 1e-9);
+        // over the top, and the head points back down into the vertex
+        assert!(arrow.path.iter().all(|p| p[1] > center[1]));
+        assert!(arrow.path.iter().any(|p| p[1] > center[1] + radius));
+        let base_center = *arrow.path.last().unwrap();
+        assert!(distance(base_center, center) > radius);
+    }
+
+    #[test]
+    fn distance_to_covers_the_head() {
+        let arrow = arrow([0.0, 0.0], [100.0, 0.0], 10.0, 15.0, 8.0);
+        assert!((arrow.distance_to([50.0, 5.0]) - 5.0).abs() < 1e-9);
+        assert!((arrow.distance_to([-3.0, 4.0]) - 5.0).abs() < 1e-9);
+        assert!((arrow.distance_to([95.0, 0.0]) - 5.0).abs() < 1e-9);
+        assert!(arrow.distance_to([86.0, 0.0]) < 1e-9);
+    }
+
+    #[test]
+    fn labels_go_left_of_the_middle() {
+        let right = arrow([0.0, 0.0], [100.0, 0.0], 0.0, 15.0, 10.0);
+        assert_close(right.label_position(12.0), [45.0, 12.0]);
+        let down = arrow([0.0, 0.0], [0.0, -100.0], 0.0, 15.0, 10.0);
+        assert_close(down.label_position(12.0), [12.0, -45.0]);
+    }
+
+    #[test]
+    fn curves_end_at_the_outline_too() {
+        let end = [100.0, 0.0];
+        let curve = curved_arrow([0.0, 0.0], end, 10.0, 20.0, 15.0, 8.0);
+        assert!(curve.is_finite());
+        // within how straight the pieces of the curve are
+        assert!((distance(curve.head[0], end) - 10.0).abs() < 0.1);
+        assert!((distance(*curve.path.last().unwrap(), end) - 18.0).abs() < 0.1);
+        // bowed out by `bend` halfway along
+        assert_close(curve.path[CURVE_SEGMENTS / 2], [50.0, 20.0]);
+
+        // and straight when the vertices overlap
+        assert_eq!(curved_arrow([0.0, 0.0], [15.0, 0.0], 10.0, 20.0, 15.0, 8.0),
+                   arrow([0.0, 0.0], [15.0, 0.0], 10.0, 15.0, 8.0));
+    }
+
+    #[test]
+    fn edges_both_ways_bend_apart() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_owned(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_owned(), [100.0, 0.0]);
+        let c = graph.add_vertex("c".to_owned(), [0.0, 100.0]);
+        let ab = graph.add_edge(a, b).unwrap();
+        let ba = graph.add_edge(b, a).unwrap();
+        let ac = graph.add_edge(a, c).unwrap();
+
+        let there = edge_arrow(&graph, ab, 10.0, 15.0, 8.0).unwrap();
+        let back = edge_arrow(&graph, ba, 10.0, 15.0, 8.0).unwrap();
+        assert!(there.path.iter().all(|p| p[1] >= 0.0));
+        assert!(back.path.iter().all(|p| p[1] <= 0.0));
+        assert!(there.label_position(0.0)[1] > 1.0);
+        assert!(back.label_position(0.0)[1] < -1.0);
+
+        // an edge on its own stays straight
+        assert_eq!(edge_arrow(&graph, ac, 10.0, 15.0, 8.0).unwrap(),
+                   arrow([0.0, 0.0], [0.0, 100.0], 10.0, 15.0, 8.0));
+    }
+
+    #[test]
+    fn zooming_keeps_the_cursor_still() {
+        let mut view = View { pan: [10.0, -20.0], zoom: 1.5 };
+        let cursor = [200.0, 100.0];
+        let under = view.to_graph(cursor);
+        assert_close(view.to_screen(under), cursor);
+
+        view.zoom_around(cursor, 2.0);
+        assert!((view.zoom - 3.0).abs() < 1e-9);
+        assert_close(view.to_screen(under), cursor);
+        view.zoom_around(cursor, 0.25);
+        assert_close(view.to_graph(cursor), under);
+    }
+}
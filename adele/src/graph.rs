@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, HashSet};
+
+
+/// Identifies a vertex for as long as it stays in its graph.
+/// IDs are never reused, so a stale ID simply fails to look anything up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VertexId(usize);
+
+/// Identifies an edge for as long as it stays in its graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EdgeId(usize);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vertex {
+    pub label: String,
+    pub position: [f64; 2],
+
+    outs: Vec<EdgeId>,
+    ins: Vec<EdgeId>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    /// Empty when the edge has no label.
+    pub label: String,
+
+    source: VertexId,
+    target: VertexId
+}
+
+/// A directed graph without redundant edges.
+///
+/// Adjacency is stored as edge IDs on both endpoints. Every operation that
+/// adds or removes something keeps the `ins` and `outs` lists of all touched
+/// vertices consistent, which is why those lists are only readable from outside.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Graph {
+    vertices: BTreeMap<VertexId, Vertex>,
+    edges: BTreeMap<EdgeId, Edge>,
+    next_vertex: usize,
+    next_edge: usize
+}
+
+impl Vertex {
+    /// The edges leaving this vertex, in the order they were added.
+    pub fn outs(&self) -> &[EdgeId] {
+        &self.outs
+    }
+
+    /// The edges entering this vertex, in the order they were added.
+    pub fn ins(&self) -> &[EdgeId] {
+        &self.ins
+    }
+}
+
+impl Edge {
+    pub fn source(&self) -> VertexId {
+        self.source
+    }
+
+    pub fn target(&self) -> VertexId {
+        self.target
+    }
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn vertex(&self, id: VertexId) -> Option<&Vertex> {
+        self.vertices.get(&id)
+    }
+
+    pub fn vertex_mut(&mut self, id: VertexId) -> Option<&mut Vertex> {
+        self.vertices.get_mut(&id)
+    }
+
+    pub fn edge(&self, id: EdgeId) -> Option<&Edge> {
+        self.edges.get(&id)
+    }
+
+    pub fn edge_mut(&mut self, id: EdgeId) -> Option<&mut Edge> {
+        self.edges.get_mut(&id)
+    }
+
+    /// All vertices in the order they were created.
+    pub fn vertices<'a>(&'a self) -> Vertices<'a> {
+        Vertices { inner: self.vertices.iter() }
+    }
+
+    /// All edges in the order they were created.
+    pub fn edges<'a>(&'a self) -> Edges<'a> {
+        Edges { inner: self.edges.iter() }
+    }
+
+    pub fn vertex_ids(&self) -> Vec<VertexId> {
+        self.vertices.keys().cloned().collect()
+    }
+
+    /// Finds the edge going from `source` to `target`, if there is one.
+    pub fn find_edge(&self, source: VertexId, target: VertexId) -> Option<EdgeId> {
+        self.vertices.get(&source).and_then(|v| {
+            v.outs.iter().cloned().find(|e| self.edges[e].target == target)
+        })
+    }
+
+    pub fn add_vertex(&mut self, label: String, position: [f64; 2]) -> VertexId {
+        let id = VertexId(self.next_vertex);
+        self.next_vertex += 1;
+        self.vertices.insert(id, Vertex {
+            label,
+            position,
+            outs: vec![],
+            ins: vec![]
+        });
+        id
+    }
+
+    /// Puts back a vertex that was removed, under its old ID.
+    /// Returns false if the ID is in use.
+    pub fn restore_vertex(&mut self, id: VertexId, label: String, position: [f64; 2]) -> bool {
+        if self.vertices.contains_key(&id) {
+            return false;
+        }
+        self.next_vertex = ::std::cmp::max(self.next_vertex, id.0 + 1);
+        self.vertices.insert(id, Vertex {
+            label,
+            position,
+            outs: vec![],
+            ins: vec![]
+        });
+        true
+    }
+
+    /// Adds an edge from `source` to `target`.
+    ///
+    /// Returns `None` without changing anything if either endpoint doesn't exist
+    /// or the graph already has an edge between them in that direction.
+    pub fn add_edge(&mut self, source: VertexId, target: VertexId) -> Option<EdgeId> {
+        if !self.vertices.contains_key(&source) || !self.vertices.contains_key(&target) {
+            return None;
+        }
+        if self.find_edge(source, target).is_some() {
+            return None;
+        }
+
+        let id = EdgeId(self.next_edge);
+        self.next_edge += 1;
+        self.link_edge(id, source, target, String::new());
+        Some(id)
+    }
+
+    fn link_edge(&mut self, id: EdgeId, source: VertexId, target: VertexId, label: String) {
+        self.edges.insert(id, Edge { label, source, target });
+        self.vertices.get_mut(&source).unwrap().outs.push(id);
+        self.vertices.get_mut(&target).unwrap().ins.push(id);
+    }
+
+    /// Puts back an edge that was removed, under its old ID.
+    /// Returns false if the ID is in use or `add_edge` would refuse the edge.
+    pub fn restore_edge(&mut self, id: EdgeId, source: VertexId, target: VertexId, label: String) -> bool {
+        if self.edges.contains_key(&id) ||
+           !self.vertices.contains_key(&source) || !self.vertices.contains_key(&target) ||
+           self.find_edge(source, target).is_some() {
+            return false;
+        }
+
+        self.next_edge = ::std::cmp::max(self.next_edge, id.0 + 1);
+        self.link_edge(id, source, target, label);
+        true
+    }
+
+    /// Removes an edge and unlinks it from both of its endpoints.
+    pub fn remove_edge(&mut self, id: EdgeId) -> Option<Edge> {
+        let edge = self.edges.remove(&id)?;
+
+        if let Some(source) = self.vertices.get_mut(&edge.source) {
+            source.outs.retain(|&e| e != id);
+        }
+        if let Some(target) = self.vertices.get_mut(&edge.target) {
+            target.ins.retain(|&e| e != id);
+        }
+        Some(edge)
+    }
+
+    /// Removes a vertex together with every edge that touches it.
+    /// The removed edges are returned alongside the vertex.
+    pub fn remove_vertex(&mut self, id: VertexId) -> Option<(Vertex, Vec<(EdgeId, Edge)>)> {
+        let incident: Vec<EdgeId> = match self.vertices.get(&id) {
+            Some(v) => v.outs.iter().chain(v.ins.iter()).cloned().collect(),
+            None => return None
+        };
+
+        let mut removed = vec![];
+        for e in incident {
+            // A self-loop shows up in both lists, so it may already be gone.
+            if let Some(edge) = self.remove_edge(e) {
+                removed.push((e, edge));
+            }
+        }
+
+        self.vertices.remove(&id).map(|v| (v, removed))
+    }
+
+    /// Copies the given vertices and the edges among them into a new graph.
+    /// IDs that aren't in the graph are skipped.
+    pub fn subgraph(&self, ids: &[VertexId]) -> Graph {
+        let mut sub = Graph::new();
+        let mut mapping = BTreeMap::new();
+        for &id in ids {
+            if let Some(v) = self.vertices.get(&id) {
+                mapping.entry(id).or_insert_with(|| sub.add_vertex(v.label.clone(), v.position));
+            }
+        }
+        for (_, e) in self.edges() {
+            if let (Some(&s), Some(&t)) = (mapping.get(&e.source), mapping.get(&e.target)) {
+                if let Some(id) = sub.add_edge(s, t) {
+                    sub.edges.get_mut(&id).unwrap().label = e.label.clone();
+                }
+            }
+        }
+        sub
+    }
+
+    /// Adds a copy of every vertex and edge of `other`, moved by `offset`.
+    /// Returns the IDs of the new vertices, in the creation order of `other`.
+    pub fn add_graph(&mut self, other: &Graph, offset: [f64; 2]) -> Vec<VertexId> {
+        let mut mapping = BTreeMap::new();
+        let mut added = vec![];
+        for (other_id, v) in other.vertices() {
+            let position = [v.position[0] + offset[0], v.position[1] + offset[1]];
+            let id = self.add_vertex(v.label.clone(), position);
+            mapping.insert(other_id, id);
+            added.push(id);
+        }
+        for (_, e) in other.edges() {
+            if let Some(id) = self.add_edge(mapping[&e.source], mapping[&e.target]) {
+                self.edges.get_mut(&id).unwrap().label = e.label.clone();
+            }
+        }
+        added
+    }
+
+    /// Changes this graph to have the same labels and edges as `other`, keeping
+    /// the IDs and positions of vertices that are still there.
+    ///
+    /// Vertices are matched up by label, in creation order when several share
+    /// one. Vertices of `other` without a match are added at their position in
+    /// `other`, and their new IDs are returned so the caller can place them.
+    pub fn sync_from(&mut self, other: &Graph) -> Vec<VertexId> {
+        let mut unmatched = self.vertex_ids();
+        let mut mapping = BTreeMap::new();
+        let mut added = vec![];
+
+        for (other_id, v) in other.vertices() {
+            let found = unmatched.iter().position(|&id| self.vertices[&id].label == v.label);
+            let id = match found {
+                Some(index) => unmatched.remove(index),
+                None => {
+                    let id = self.add_vertex(v.label.clone(), v.position);
+                    added.push(id);
+                    id
+                }
+            };
+            mapping.insert(other_id, id);
+        }
+
+        for id in unmatched {
+            self.remove_vertex(id);
+        }
+
+        let wanted: HashSet<(VertexId, VertexId)> = other.edges()
+            .map(|(_, e)| (mapping[&e.source], mapping[&e.target]))
+            .collect();
+        let stale: Vec<EdgeId> = self.edges()
+            .filter(|&(_, e)| !wanted.contains(&(e.source, e.target)))
+            .map(|(id, _)| id)
+            .collect();
+        for id in stale {
+            self.remove_edge(id);
+        }
+        for (_, e) in other.edges() {
+            let (source, target) = (mapping[&e.source], mapping[&e.target]);
+            let id = self.add_edge(source, target).or_else(|| self.find_edge(source, target)).unwrap();
+            self.edges.get_mut(&id).unwrap().label = e.label.clone();
+        }
+
+        added
+    }
+}
+
+pub struct Vertices<'a> {
+    inner: ::std::collections::btree_map::Iter<'a, VertexId, Vertex>
+}
+
+impl<'a> Iterator for Vertices<'a> {
+    type Item = (VertexId, &'a Vertex);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(&id, v)| (id, v))
+    }
+}
+
+pub struct Edges<'a> {
+    inner: ::std::collections::btree_map::Iter<'a, EdgeId, Edge>
+}
+
+impl<'a> Iterator for Edges<'a> {
+    type Item = (EdgeId, &'a Edge);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(&id, e)| (id, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every edge is listed once by each endpoint, and nothing else is.
+    fn assert_consistent(graph: &Graph) {
+        for (id, e) in graph.edges() {
+            let source = graph.vertex(e.source()).expect("edge from a missing vertex");
+            let target = graph.vertex(e.target()).expect("edge to a missing vertex");
+            assert_eq!(source.outs().iter().filter(|&&o| o == id).count(), 1);
+            assert_eq!(target.ins().iter().filter(|&&i| i == id).count(), 1);
+        }
+        for (id, v) in graph.vertices() {
+            assert!(v.outs().iter().all(|&e| graph.edge(e).map(|e| e.source()) == Some(id)));
+            assert!(v.ins().iter().all(|&e| graph.edge(e).map(|e| e.target()) == Some(id)));
+        }
+        let outs: usize = graph.vertices().map(|(_, v)| v.outs().len()).sum();
+        let ins: usize = graph.vertices().map(|(_, v)| v.ins().len()).sum();
+        assert_eq!((outs, ins), (graph.edge_count(), graph.edge_count()));
+    }
+
+    fn triangle() -> (Graph, Vec<VertexId>) {
+        let mut graph = Graph::new();
+        let ids: Vec<VertexId> = ["a", "b", "c"].iter().map(|l| graph.add_vertex(l.to_string(), [0.0, 0.0])).collect();
+        graph.add_edge(ids[0], ids[1]);
+        graph.add_edge(ids[1], ids[2]);
+        graph.add_edge(ids[2], ids[0]);
+        (graph, ids)
+    }
+
+    #[test]
+    fn adjacency_stays_consistent() {
+        let (mut graph, ids) = triangle();
+        assert_consistent(&graph);
+
+        let e = graph.find_edge(ids[1], ids[2]).unwrap();
+        let removed = graph.remove_edge(e).unwrap();
+        assert_consistent(&graph);
+        assert!(graph.remove_edge(e).is_none());
+
+        let (vertex, edges) = graph.remove_vertex(ids[0]).unwrap();
+        assert_consistent(&graph);
+        assert_eq!((graph.vertex_count(), graph.edge_count(), edges.len()), (2, 0, 2));
+
+        assert!(graph.restore_vertex(ids[0], vertex.label, vertex.position));
+        for (id, edge) in edges {
+            assert!(graph.restore_edge(id, edge.source(), edge.target(), edge.label));
+        }
+        assert!(graph.restore_edge(e, removed.source(), removed.target(), removed.label));
+        assert_consistent(&graph);
+        assert_eq!(graph, triangle().0);
+    }
+
+    #[test]
+    fn removing_a_vertex_with_a_self_loop() {
+        let (mut graph, ids) = triangle();
+        let self_loop = graph.add_edge(ids[0], ids[0]).unwrap();
+        assert_consistent(&graph);
+
+        let (_, edges) = graph.remove_vertex(ids[0]).unwrap();
+        let removed: Vec<EdgeId> = edges.iter().map(|&(id, _)| id).collect();
+        assert_eq!(removed.len(), 3, "the self-loop is only removed once");
+        assert!(removed.contains(&self_loop));
+        assert_consistent(&graph);
+    }
+
+    #[test]
+    fn duplicate_edges_are_refused() {
+        let (mut graph, ids) = triangle();
+        let before = graph.clone();
+        assert_eq!(graph.add_edge(ids[0], ids[1]), None);
+        assert!(graph.add_edge(ids[1], ids[0]).is_some(), "the other direction is a different edge");
+
+        let e = graph.find_edge(ids[1], ids[0]).unwrap();
+        graph.remove_edge(e);
+        let taken = graph.find_edge(ids[0], ids[1]).unwrap();
+        assert!(!graph.restore_edge(taken, ids[1], ids[0], String::new()), "the ID is in use");
+        assert!(!graph.restore_edge(e, ids[0], ids[1], String::new()), "the edge is already there");
+        assert!(graph.edges().eq(before.edges()));
+
+        let gone = graph.add_vertex("d".to_string(), [0.0, 0.0]);
+        graph.remove_vertex(gone);
+        assert_eq!(graph.add_edge(ids[0], gone), None);
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let (mut graph, ids) = triangle();
+        graph.remove_vertex(ids[2]);
+        let d = graph.add_vertex("d".to_string(), [0.0, 0.0]);
+        assert!(!ids.contains(&d));
+        assert!(graph.vertex(ids[2]).is_none());
+        assert!(!graph.restore_vertex(d, String::new(), [0.0, 0.0]));
+    }
+
+    #[test]
+    fn sync_keeps_ids_and_positions() {
+        let (mut graph, ids) = triangle();
+        for (n, &id) in ids.iter().enumerate() {
+            graph.vertex_mut(id).unwrap().position = [n as f64, 10.0];
+        }
+
+        // drop `b`, add `d`, and change the edges and an edge label
+        let mut other = Graph::new();
+        let a = other.add_vertex("a".to_string(), [-1.0, -1.0]);
+        let c = other.add_vertex("c".to_string(), [-1.0, -1.0]);
+        let d = other.add_vertex("d".to_string(), [7.0, 8.0]);
+        let ca = other.add_edge(c, a).unwrap();
+        other.edge_mut(ca).unwrap().label = "back".to_string();
+        other.add_edge(a, d);
+
+        let added = graph.sync_from(&other);
+        assert_consistent(&graph);
+        assert_eq!(added.len(), 1);
+        assert_eq!(graph.vertex(ids[0]).unwrap().position, [0.0, 10.0]);
+        assert_eq!(graph.vertex(ids[2]).unwrap().position, [2.0, 10.0]);
+        assert!(graph.vertex(ids[1]).is_none());
+        assert_eq!(graph.vertex(added[0]).unwrap().position, [7.0, 8.0]);
+
+        let edges: Vec<(VertexId, VertexId, String)> = graph.edges()
+            .map(|(_, e)| (e.source(), e.target(), e.label.clone())).collect();
+        assert_eq!(edges, vec![(ids[2], ids[0], "back".to_string()), (ids[0], added[0], String::new())]);
+    }
+
+    #[test]
+    fn sync_matches_repeated_labels_in_order() {
+        let mut graph = Graph::new();
+        let first = graph.add_vertex("x".to_string(), [1.0, 0.0]);
+        let second = graph.add_vertex("x".to_string(), [2.0, 0.0]);
+        let mut other = Graph::new();
+        other.add_vertex("x".to_string(), [0.0, 0.0]);
+
+        assert!(graph.sync_from(&other).is_empty());
+        assert!(graph.vertex(first).is_some());
+        assert!(graph.vertex(second).is_none());
+    }
+}
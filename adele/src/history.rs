@@ -0,0 +1,425 @@
+//! Undo and redo.
+//!
+//! Every edit is recorded as a `Change` after it has been made to the graph.
+//! A change holds enough to make it again or take it back, and refers to
+//! vertices and edges by ID. Undoing a removal restores the old IDs, so
+//! changes further up or down the stack stay valid.
+
+use graph::{Edge, EdgeId, Graph, VertexId};
+
+
+#[derive(Clone, Debug)]
+pub enum Change {
+    AddVertex { id: VertexId, label: String, position: [f64; 2] },
+    // `edges` are the edges that went with the vertex, as `Graph::remove_vertex` returns them.
+    RemoveVertex { id: VertexId, label: String, position: [f64; 2],
+                   edges: Vec<(EdgeId, Edge)> },
+    MoveVertex { id: VertexId, from: [f64; 2], to: [f64; 2] },
+    SetLabel { id: VertexId, from: String, to: String },
+    AddEdge { id: EdgeId, source: VertexId, target: VertexId },
+    RemoveEdge { id: EdgeId, source: VertexId, target: VertexId, label: String },
+    SetEdgeLabel { id: EdgeId, from: String, to: String },
+    // Several changes made as a single step, like deleting a selection, in the order they were made.
+    Batch(Vec<Change>),
+    // Edits that touch too much to describe piece by piece, like syncing from text.
+    Replace { before: Box<Graph>, after: Box<Graph> }
+}
+
+impl Change {
+    /// Makes the change to a graph that is in the state from before it.
+    pub fn redo(&self, graph: &mut Graph) {
+        match *self {
+            Change::AddVertex { id, ref label, position } => {
+                graph.restore_vertex(id, label.clone(), position);
+            },
+            Change::RemoveVertex { id, .. } => {
+                graph.remove_vertex(id);
+            },
+            Change::MoveVertex { id, to, .. } => {
+                if let Some(v) = graph.vertex_mut(id) {
+                    v.position = to;
+                }
+            },
+            Change::SetLabel { id, ref to, .. } => {
+                if let Some(v) = graph.vertex_mut(id) {
+                    v.label = to.clone();
+                }
+            },
+            Change::AddEdge { id, source, target } => {
+                graph.restore_edge(id, source, target, String::new());
+            },
+            Change::RemoveEdge { id, .. } => {
+                graph.remove_edge(id);
+            },
+            Change::SetEdgeLabel { id, ref to, .. } => {
+                if let Some(e) = graph.edge_mut(id) {
+                    e.label = to.clone();
+                }
+            },
+            Change::Batch(ref changes) => {
+                for change in changes.iter() {
+                    change.redo(graph);
+                }
+            },
+            Change::Replace { ref after, .. } => *graph = (**after).clone()
+        }
+    }
+
+    /// Takes the change back from a graph that is in the state from after it.
+    pub fn undo(&self, graph: &mut Graph) {
+        match *self {
+            Change::AddVertex { id, .. } => {
+                graph.remove_vertex(id);
+            },
+            Change::RemoveVertex { id, ref label, position, ref edges } => {
+                graph.restore_vertex(id, label.clone(), position);
+                for &(id, ref edge) in edges.iter() {
+                    graph.restore_edge(id, edge.source(), edge.target(), edge.label.clone());
+                }
+            },
+            Change::MoveVertex { id, from, .. } => {
+                if let Some(v) = graph.vertex_mut(id) {
+                    v.position = from;
+                }
+            },
+            Change::SetLabel { id, ref from, .. } => {
+                if let Some(v) = graph.vertex_mut(id) {
+                    v.label = from.clone();
+                }
+            },
+            Change::AddEdge { id, .. } => {
+                graph.remove_edge(id);
+            },
+            Change::RemoveEdge { id, source, target, ref label } => {
+                graph.restore_edge(id, source, target, label.clone());
+            },
+            Change::SetEdgeLabel { id, ref from, .. } => {
+                if let Some(e) = graph.edge_mut(id) {
+                    e.label = from.clone();
+                }
+            },
+            Change::Batch(ref changes) => {
+                for change in changes.iter().rev() {
+                    change.undo(graph);
+                }
+            },
+            Change::Replace { ref before, .. } => *graph = (**before).clone()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    revision: u64,
+    // Whether the last change was typed and more typing can still merge into it.
+    typing: bool
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Adds a change that was just made. This forgets everything that could be redone.
+    pub fn record(&mut self, change: Change) {
+        self.revision += 1;
+        self.redo.clear();
+        self.typing = false;
+        self.undo.push(change);
+    }
+
+    /// Like `record`, for a change made by typing into a label, or a `Replace`
+    /// made by typing into the text of the whole graph.
+    ///
+    /// Typing produces one change per keystroke, so until `finish_typing` is
+    /// called or something else is recorded, changes to the same label are
+    /// merged into one, and so are replacements.
+    pub fn record_typing(&mut self, change: Change) {
+        self.revision += 1;
+        self.redo.clear();
+
+        let last = if self.typing { self.undo.last_mut() } else { None };
+        let merged = match (&change, last) {
+            (&Change::SetLabel { id, ref to, .. },
+             Some(&mut Change::SetLabel { id: last_id, to: ref mut last_to, .. })) if last_id == id => {
+                *last_to = to.clone();
+                true
+            },
+            (&Change::SetEdgeLabel { id, ref to, .. },
+             Some(&mut Change::SetEdgeLabel { id: last_id, to: ref mut last_to, .. })) if last_id == id => {
+                *last_to = to.clone();
+                true
+            },
+            (Change::Replace { after, .. }, Some(&mut Change::Replace { after: ref mut last_after, .. })) => {
+                *last_after = after.clone();
+                true
+            },
+            _ => false
+        };
+        self.typing = true;
+        if merged {
+            return;
+        }
+        self.undo.push(change);
+    }
+
+    /// Ends the typing that `record_typing` merges, e.g. when the label loses focus.
+    /// Typing into the same label again after this is a change of its own.
+    pub fn finish_typing(&mut self) {
+        self.typing = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Takes back the most recent change. Returns false if there was nothing to undo.
+    pub fn undo(&mut self, graph: &mut Graph) -> bool {
+        match self.undo.pop() {
+            Some(change) => {
+                self.revision += 1;
+                self.typing = false;
+                change.undo(graph);
+                self.redo.push(change);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Makes the most recently undone change again. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, graph: &mut Graph) -> bool {
+        match self.redo.pop() {
+            Some(change) => {
+                self.revision += 1;
+                self.typing = false;
+                change.redo(graph);
+                self.undo.push(change);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.revision += 1;
+        self.typing = false;
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Goes up with every change that is recorded, undone or redone, and when the
+    /// history is cleared. When every edit is recorded, this tells whether the
+    /// graph may have changed without having to compare it.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_are_one_step() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_string(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_string(), [1.0, 0.0]);
+        let e = graph.add_edge(a, b).unwrap();
+        graph.edge_mut(e).unwrap().label = "ab".to_string();
+        let before = graph.clone();
+
+        // remove the edge, then a vertex, then move the other one
+        let removed = graph.remove_edge(e).unwrap();
+        let (vertex, edges) = graph.remove_vertex(a).unwrap();
+        graph.vertex_mut(b).unwrap().position = [5.0, 5.0];
+        let after = graph.clone();
+
+        let mut history = History::new();
+        history.record(Change::Batch(vec![
+            Change::RemoveEdge { id: e, source: a, target: b, label: removed.label },
+            Change::RemoveVertex { id: a, label: vertex.label, position: vertex.position, edges },
+            Change::MoveVertex { id: b, from: [1.0, 0.0], to: [5.0, 5.0] }
+        ]));
+
+        assert!(history.undo(&mut graph));
+        assert_eq!(graph, before);
+        assert!(!history.can_undo());
+        assert!(history.redo(&mut graph));
+        assert_eq!(graph, after);
+    }
+
+    /// Types `text` into the label of `id` one character at a time, as the widget records it.
+    fn type_label(history: &mut History, graph: &mut Graph, id: VertexId, text: &str) {
+        for c in text.chars() {
+            let from = graph.vertex(id).unwrap().label.clone();
+            let to = from.clone() + &c.to_string();
+            graph.vertex_mut(id).unwrap().label = to.clone();
+            history.record_typing(Change::SetLabel { id, from, to });
+        }
+    }
+
+    #[test]
+    fn typing_merges_until_it_is_finished() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex(String::new(), [0.0, 0.0]);
+        let mut history = History::new();
+
+        type_label(&mut history, &mut graph, a, "abc");
+        history.finish_typing();
+        type_label(&mut history, &mut graph, a, "de");
+        assert!(history.undo(&mut graph));
+        assert_eq!(graph.vertex(a).unwrap().label, "abc");
+        assert!(history.undo(&mut graph));
+        assert_eq!(graph.vertex(a).unwrap().label, "");
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn other_changes_end_typing() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex(String::new(), [0.0, 0.0]);
+        let b = graph.add_vertex(String::new(), [0.0, 0.0]);
+        let mut history = History::new();
+
+        type_label(&mut history, &mut graph, a, "x");
+        type_label(&mut history, &mut graph, b, "y");
+        type_label(&mut history, &mut graph, a, "z");
+        graph.vertex_mut(b).unwrap().position = [1.0, 1.0];
+        history.record(Change::MoveVertex { id: b, from: [0.0, 0.0], to: [1.0, 1.0] });
+        type_label(&mut history, &mut graph, a, "w");
+
+        let mut steps = 0;
+        while history.undo(&mut graph) {
+            steps += 1;
+        }
+        assert_eq!(steps, 5);
+        assert_eq!(graph.vertex(a).unwrap().label, "");
+    }
+
+    /// Whether two graphs have the same vertices and edges. The IDs they would hand out
+    /// next don't matter, and neither does the order of the edges at each vertex.
+    fn same(a: &Graph, b: &Graph) -> bool {
+        let vertices = |graph: &Graph| -> Vec<_> {
+            graph.vertices().map(|(id, v)| {
+                let (mut outs, mut ins) = (v.outs().to_vec(), v.ins().to_vec());
+                outs.sort();
+                ins.sort();
+                (id, v.label.clone(), v.position, outs, ins)
+            }).collect()
+        };
+        vertices(a) == vertices(b) && a.edges().eq(b.edges())
+    }
+
+    /// Makes an edit, records the change it returns, and checks that undoing and
+    /// redoing it go back and forth between the graphs from before and after.
+    fn assert_round_trip<F>(mut graph: Graph, edit: F) where F: FnOnce(&mut Graph) -> Change {
+        let before = graph.clone();
+        let change = edit(&mut graph);
+        let after = graph.clone();
+        assert!(!same(&before, &after), "{:?} didn't change anything", change);
+
+        let mut history = History::new();
+        history.record(change);
+        for _ in 0..2 {
+            assert!(history.undo(&mut graph));
+            assert!(same(&graph, &before));
+            assert!(history.redo(&mut graph));
+            assert!(same(&graph, &after));
+        }
+    }
+
+    /// `a => b : ab; b => c; c => c;`
+    fn example() -> (Graph, VertexId, VertexId, VertexId) {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_string(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_string(), [1.0, 0.0]);
+        let c = graph.add_vertex("c".to_string(), [2.0, 0.0]);
+        let ab = graph.add_edge(a, b).unwrap();
+        graph.edge_mut(ab).unwrap().label = "ab".to_string();
+        graph.add_edge(b, c);
+        graph.add_edge(c, c);
+        (graph, a, b, c)
+    }
+
+    #[test]
+    fn vertex_changes_round_trip() {
+        let (graph, a, b, c) = example();
+        assert_round_trip(graph.clone(), |graph| {
+            let id = graph.add_vertex("d".to_string(), [3.0, 3.0]);
+            Change::AddVertex { id, label: "d".to_string(), position: [3.0, 3.0] }
+        });
+        for &id in [a, b, c].iter() {
+            // `c` has a self-loop, and `b` edges both ways
+            assert_round_trip(graph.clone(), |graph| {
+                let (vertex, edges) = graph.remove_vertex(id).unwrap();
+                Change::RemoveVertex { id, label: vertex.label, position: vertex.position, edges }
+            });
+        }
+        assert_round_trip(graph.clone(), |graph| {
+            graph.vertex_mut(b).unwrap().position = [5.0, -5.0];
+            Change::MoveVertex { id: b, from: [1.0, 0.0], to: [5.0, -5.0] }
+        });
+        assert_round_trip(graph.clone(), |graph| {
+            graph.vertex_mut(a).unwrap().label = "alpha".to_string();
+            Change::SetLabel { id: a, from: "a".to_string(), to: "alpha".to_string() }
+        });
+    }
+
+    #[test]
+    fn edge_changes_round_trip() {
+        let (graph, a, b, c) = example();
+        assert_round_trip(graph.clone(), |graph| {
+            let id = graph.add_edge(c, a).unwrap();
+            Change::AddEdge { id, source: c, target: a }
+        });
+        for &(source, target) in [(a, b), (c, c)].iter() {
+            assert_round_trip(graph.clone(), |graph| {
+                let id = graph.find_edge(source, target).unwrap();
+                let removed = graph.remove_edge(id).unwrap();
+                Change::RemoveEdge { id, source, target, label: removed.label }
+            });
+        }
+        assert_round_trip(graph.clone(), |graph| {
+            let id = graph.find_edge(a, b).unwrap();
+            graph.edge_mut(id).unwrap().label = "likes".to_string();
+            Change::SetEdgeLabel { id, from: "ab".to_string(), to: "likes".to_string() }
+        });
+    }
+
+    #[test]
+    fn replacing_round_trips() {
+        let (graph, ..) = example();
+        assert_round_trip(graph, |graph| {
+            let before = Box::new(graph.clone());
+            *graph = example().0;
+            graph.add_vertex("e".to_string(), [9.0, 9.0]);
+            Change::Replace { before, after: Box::new(graph.clone()) }
+        });
+    }
+
+    #[test]
+    fn typed_replacements_merge() {
+        let mut graph = Graph::new();
+        let mut history = History::new();
+        for label in ["a", "ab", "abc"].iter() {
+            let before = Box::new(graph.clone());
+            graph = Graph::new();
+            graph.add_vertex(label.to_string(), [0.0, 0.0]);
+            history.record_typing(Change::Replace { before, after: Box::new(graph.clone()) });
+        }
+        history.finish_typing();
+
+        assert!(history.undo(&mut graph));
+        assert_eq!(graph, Graph::new());
+        assert!(!history.can_undo());
+        assert!(history.redo(&mut graph));
+        assert_eq!(graph.vertices().map(|(_, v)| v.label.clone()).collect::<Vec<_>>(), vec!["abc"]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Spring embedder layout, after Fruchterman and Reingold.
+//!
+//! Every pair of vertices pushes apart, every edge pulls its endpoints together,
+//! and a pull towards the middle keeps unconnected parts from drifting to
+//! the walls. How far a vertex may move per step is limited by a temperature,
+//! which `run` lowers until the layout settles.
+
+use graph::{Graph, VertexId};
+use layout::Bounds;
+
+
+const COOLING: f64 = 0.95;
+const MAX_STEPS: usize = 300;
+// Once no vertex moves further than this in a step, `run` stops.
+const SETTLED: f64 = 0.5;
+const GRAVITY: f64 = 1.0;
+
+#[derive(Clone, Debug)]
+pub struct ForceLayout {
+    /// Vertices are kept this far inside the bounds, and at least twice this far apart.
+    pub radius: f64,
+    pub bounds: Bounds,
+    /// The length edges settle at when nothing else pulls on them.
+    pub edge_length: f64,
+    /// How far a vertex may move in one step.
+    pub temperature: f64
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn length(v: [f64; 2]) -> f64 {
+    (v[0]*v[0] + v[1]*v[1]).sqrt()
+}
+
+impl ForceLayout {
+    pub fn new(radius: f64, bounds: Bounds) -> Self {
+        ForceLayout {
+            radius,
+            bounds,
+            edge_length: 4.0 * radius,
+            temperature: bounds.width().min(bounds.height()) / 10.0
+        }
+    }
+
+    /// Lays the whole graph out, starting from the current positions.
+    pub fn run(&self, graph: &mut Graph) {
+        let mut temperature = self.temperature;
+        for _ in 0..MAX_STEPS {
+            if self.step_at(graph, &[], temperature) < SETTLED {
+                break;
+            }
+            temperature *= COOLING;
+        }
+    }
+
+    /// Moves every vertex once along the forces acting on it, except for the
+    /// `pinned` ones, which still push and pull on the others.
+    /// Returns the furthest any vertex moved, which is small once the layout is stable.
+    pub fn step(&self, graph: &mut Graph, pinned: &[VertexId]) -> f64 {
+        self.step_at(graph, pinned, self.temperature)
+    }
+
+    fn step_at(&self, graph: &mut Graph, pinned: &[VertexId], temperature: f64) -> f64 {
+        let ids = graph.vertex_ids();
+        let positions: Vec<[f64; 2]> = ids.iter().map(|&id| graph.vertex(id).unwrap().position).collect();
+        let index_of = |id: VertexId| ids.binary_search(&id).unwrap();
+        let mut displacement = vec![[0.0, 0.0]; ids.len()];
+
+        let k = self.edge_length;
+        let min_distance = 2.0 * self.radius;
+
+        for i in 0..ids.len() {
+            for j in (i + 1)..ids.len() {
+                let mut delta = sub(positions[i], positions[j]);
+                let mut distance = length(delta);
+                if distance < 1e-6 {
+                    // Vertices on top of each other have no direction to push in,
+                    // so make one up that differs from pair to pair.
+                    let angle = (i * 7 + j * 13) as f64;
+                    delta = [angle.cos() * 1e-3, angle.sin() * 1e-3];
+                    distance = 1e-3;
+                }
+
+                let mut force = k * k / distance;
+                if distance < min_distance {
+                    // overlapping circles push apart much harder
+                    force *= min_distance / distance;
+                }
+
+                let push = [delta[0] / distance * force, delta[1] / distance * force];
+                displacement[i][0] += push[0];
+                displacement[i][1] += push[1];
+                displacement[j][0] -= push[0];
+                displacement[j][1] -= push[1];
+            }
+        }
+
+        for (_, edge) in graph.edges() {
+            let (s, t) = (index_of(edge.source()), index_of(edge.target()));
+            if s == t {
+                continue;
+            }
+
+            let delta = sub(positions[s], positions[t]);
+            let distance = length(delta);
+            if distance < 1e-6 {
+                continue;
+            }
+
+            let force = distance * distance / k;
+            let pull = [delta[0] / distance * force, delta[1] / distance * force];
+            displacement[s][0] -= pull[0];
+            displacement[s][1] -= pull[1];
+            displacement[t][0] += pull[0];
+            displacement[t][1] += pull[1];
+        }
+
+        let center = self.bounds.center();
+        let mut furthest: f64 = 0.0;
+        for (i, &id) in ids.iter().enumerate() {
+            if pinned.contains(&id) {
+                continue;
+            }
+
+            let to_center = sub(center, positions[i]);
+            let d = [displacement[i][0] + to_center[0] * GRAVITY,
+                     displacement[i][1] + to_center[1] * GRAVITY];
+            let magnitude = length(d);
+            if magnitude < 1e-9 {
+                continue;
+            }
+
+            let travel = magnitude.min(temperature);
+            let moved = [positions[i][0] + d[0] / magnitude * travel,
+                         positions[i][1] + d[1] / magnitude * travel];
+            let clamped = self.bounds.clamp(moved, self.radius);
+            furthest = furthest.max(length(sub(clamped, positions[i])));
+            graph.vertex_mut(id).unwrap().position = clamped;
+        }
+
+        furthest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds::new(-500.0, 500.0, -400.0, 400.0)
+    }
+
+    fn finite(graph: &Graph) -> bool {
+        graph.vertices().all(|(_, v)| v.position[0].is_finite() && v.position[1].is_finite())
+    }
+
+    #[test]
+    fn pinned_vertices_stay() {
+        let mut graph = Graph::new();
+        let pinned = graph.add_vertex("pinned".to_string(), [100.0, 50.0]);
+        for i in 0..4 {
+            let other = graph.add_vertex(i.to_string(), [100.0 + i as f64, 50.0]);
+            graph.add_edge(pinned, other);
+        }
+        let layout = ForceLayout::new(20.0, bounds());
+        for _ in 0..20 {
+            assert!(layout.step(&mut graph, &[pinned]) > 0.0);
+            assert_eq!(graph.vertex(pinned).unwrap().position, [100.0, 50.0]);
+        }
+    }
+
+    #[test]
+    fn vertices_on_top_of_each_other_come_apart() {
+        let mut graph = Graph::new();
+        let ids: Vec<VertexId> = (0..5).map(|i| graph.add_vertex(i.to_string(), [0.0, 0.0])).collect();
+        graph.add_edge(ids[0], ids[1]);
+        graph.add_edge(ids[1], ids[1]);
+
+        ForceLayout::new(20.0, bounds()).run(&mut graph);
+        assert!(finite(&graph));
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in ids[i + 1..].iter() {
+                let (p, q) = (graph.vertex(a).unwrap().position, graph.vertex(b).unwrap().position);
+                assert!(length(sub(p, q)) > 1.0, "{:?} and {:?} are still together", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn layouts_stay_in_bounds() {
+        let mut graph = Graph::new();
+        let ids: Vec<VertexId> = (0..30).map(|i| graph.add_vertex(i.to_string(), [i as f64, 0.0])).collect();
+        for pair in ids.windows(2) {
+            graph.add_edge(pair[0], pair[1]);
+        }
+        let small = Bounds::new(0.0, 200.0, 0.0, 100.0);
+        ForceLayout::new(10.0, small).run(&mut graph);
+        assert!(finite(&graph));
+        for (_, v) in graph.vertices() {
+            assert!(small.left + 10.0 <= v.position[0] && v.position[0] <= small.right - 10.0);
+            assert!(small.bottom + 10.0 <= v.position[1] && v.position[1] <= small.top - 10.0);
+        }
+    }
+}
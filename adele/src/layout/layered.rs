@@ -0,0 +1,423 @@
+//! Layered layout for directed graphs, in the style of Sugiyama et al.
+//!
+//! The layout runs in four phases:
+//!
+//! 1. Cycle breaking: edges that close a cycle are turned around for the rest
+//!    of the layout, so every edge can point the same way.
+//! 2. Layer assignment: each vertex goes one layer past the furthest of its
+//!    predecessors. Edges spanning several layers get a dummy vertex in every
+//!    layer in between.
+//! 3. Crossing minimization: layers are sorted by the barycenter of their
+//!    neighbours, sweeping down and up, keeping the order with fewest crossings.
+//! 4. Coordinate assignment: vertices move towards their neighbours while
+//!    keeping their order and spacing, then the drawing is fitted into the bounds.
+
+use graph::Graph;
+use layout::Bounds;
+
+
+const ORDERING_SWEEPS: usize = 12;
+const STRAIGHTENING_PASSES: usize = 8;
+
+/// Which way edges point once the graph is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    TopDown,
+    LeftRight
+}
+
+#[derive(Clone, Debug)]
+pub struct LayeredLayout {
+    /// Vertices are kept this far inside the bounds.
+    pub radius: f64,
+    pub bounds: Bounds,
+    pub direction: Direction,
+    /// Distance between neighbouring layers. Shrunk if the layers don't fit in the bounds.
+    pub layer_spacing: f64,
+    /// Smallest distance between vertices in the same layer. Shrunk like `layer_spacing`.
+    pub vertex_spacing: f64
+}
+
+/// The graph being laid out, with vertices numbered by position in `Graph::vertex_ids`
+/// followed by dummy vertices.
+struct Layering {
+    layers: Vec<Vec<usize>>,
+    ups: Vec<Vec<usize>>,
+    downs: Vec<Vec<usize>>
+}
+
+impl LayeredLayout {
+    pub fn new(radius: f64, bounds: Bounds, direction: Direction) -> Self {
+        LayeredLayout {
+            radius,
+            bounds,
+            direction,
+            layer_spacing: 4.0 * radius,
+            vertex_spacing: 3.0 * radius
+        }
+    }
+
+    pub fn run(&self, graph: &mut Graph) {
+        let ids = graph.vertex_ids();
+        if ids.is_empty() {
+            return;
+        }
+
+        let mut edges = vec![];
+        for (_, edge) in graph.edges() {
+            let s = ids.binary_search(&edge.source()).unwrap();
+            let t = ids.binary_search(&edge.target()).unwrap();
+            if s != t {
+                edges.push((s, t));
+            }
+        }
+
+        let acyclic = break_cycles(ids.len(), &edges);
+        let ranks = assign_layers(ids.len(), &acyclic);
+        let mut layering = Layering::new(ids.len(), &ranks, &acyclic);
+        layering.minimize_crossings();
+        let along = layering.assign_coordinates(self.vertex_spacing);
+
+        // Place everything relative to the first layer and the leftmost vertex,
+        // then scale and move the drawing into the bounds.
+        let points: Vec<[f64; 2]> = (0..ids.len()).map(|v| {
+            let across = ranks[v] as f64 * self.layer_spacing;
+            match self.direction {
+                Direction::TopDown => [along[v], -across],
+                Direction::LeftRight => [across, -along[v]]
+            }
+        }).collect();
+
+        let min_x = points.iter().map(|p| p[0]).fold(f64::INFINITY, f64::min);
+        let max_x = points.iter().map(|p| p[0]).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = points.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+        let max_y = points.iter().map(|p| p[1]).fold(f64::NEG_INFINITY, f64::max);
+
+        let room_x = self.bounds.width() - 2.0 * self.radius;
+        let room_y = self.bounds.height() - 2.0 * self.radius;
+        let mut scale: f64 = 1.0;
+        if max_x - min_x > room_x {
+            scale = scale.min(room_x.max(0.0) / (max_x - min_x));
+        }
+        if max_y - min_y > room_y {
+            scale = scale.min(room_y.max(0.0) / (max_y - min_y));
+        }
+
+        let center = self.bounds.center();
+        let middle = [(min_x + max_x) / 2.0, (min_y + max_y) / 2.0];
+        for (v, &id) in ids.iter().enumerate() {
+            let p = [center[0] + (points[v][0] - middle[0]) * scale,
+                     center[1] + (points[v][1] - middle[1]) * scale];
+            graph.vertex_mut(id).unwrap().position = self.bounds.clamp(p, self.radius);
+        }
+    }
+}
+
+/// Returns the edges with every edge that closes a cycle turned around, and duplicates removed.
+/// An edge closes a cycle when a depth-first search reaches a vertex that's still on its stack.
+fn break_cycles(n: usize, edges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut outs = vec![vec![]; n];
+    for &(s, t) in edges {
+        outs[s].push(t);
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit { New, OnStack, Done }
+
+    let mut visit = vec![Visit::New; n];
+    let mut result = vec![];
+    for root in 0..n {
+        if visit[root] != Visit::New {
+            continue;
+        }
+
+        // (vertex, index of the next out-edge to follow)
+        let mut stack = vec![(root, 0)];
+        visit[root] = Visit::OnStack;
+        while let Some(&mut (v, ref mut next)) = stack.last_mut() {
+            if *next < outs[v].len() {
+                let w = outs[v][*next];
+                *next += 1;
+                match visit[w] {
+                    Visit::New => {
+                        result.push((v, w));
+                        visit[w] = Visit::OnStack;
+                        stack.push((w, 0));
+                    },
+                    Visit::OnStack => result.push((w, v)),
+                    Visit::Done => result.push((v, w))
+                }
+            } else {
+                visit[v] = Visit::Done;
+                stack.pop();
+            }
+        }
+    }
+
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Puts each vertex one layer below the furthest of its predecessors,
+/// so vertices without predecessors are in layer 0.
+fn assign_layers(n: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut outs = vec![vec![]; n];
+    let mut in_degree = vec![0; n];
+    for &(s, t) in edges {
+        outs[s].push(t);
+        in_degree[t] += 1;
+    }
+
+    let mut ranks = vec![0; n];
+    let mut ready: Vec<usize> = (0..n).filter(|&v| in_degree[v] == 0).collect();
+    while let Some(v) = ready.pop() {
+        for &w in outs[v].iter() {
+            ranks[w] = ranks[w].max(ranks[v] + 1);
+            in_degree[w] -= 1;
+            if in_degree[w] == 0 {
+                ready.push(w);
+            }
+        }
+    }
+    ranks
+}
+
+impl Layering {
+    fn new(n: usize, ranks: &[usize], edges: &[(usize, usize)]) -> Self {
+        let layer_count = ranks.iter().cloned().max().unwrap_or(0) + 1;
+        let mut layers = vec![vec![]; layer_count];
+        for (v, &rank) in ranks.iter().enumerate() {
+            layers[rank].push(v);
+        }
+
+        let mut layering = Layering { layers, ups: vec![vec![]; n], downs: vec![vec![]; n] };
+        for &(s, t) in edges {
+            // chain long edges through one dummy per layer they skip
+            let mut from = s;
+            for rank in (ranks[s] + 1)..ranks[t] {
+                let dummy = layering.ups.len();
+                layering.ups.push(vec![]);
+                layering.downs.push(vec![]);
+                layering.layers[rank].push(dummy);
+                layering.link(from, dummy);
+                from = dummy;
+            }
+            layering.link(from, t);
+        }
+        layering
+    }
+
+    fn link(&mut self, upper: usize, lower: usize) {
+        self.downs[upper].push(lower);
+        self.ups[lower].push(upper);
+    }
+
+    fn crossings(&self) -> usize {
+        let mut position = vec![0; self.ups.len()];
+        for layer in self.layers.iter() {
+            for (i, &v) in layer.iter().enumerate() {
+                position[v] = i;
+            }
+        }
+
+        let mut count = 0;
+        for layer in self.layers.iter() {
+            let mut edges = vec![];
+            for &v in layer.iter() {
+                for &w in self.downs[v].iter() {
+                    edges.push((position[v], position[w]));
+                }
+            }
+            for (i, &(a, b)) in edges.iter().enumerate() {
+                for &(c, d) in edges[i + 1..].iter() {
+                    if (a < c && b > d) || (a > c && b < d) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// Reorders each layer by the average position of its neighbours in the
+    /// layer before it, sweeping downwards then upwards.
+    fn minimize_crossings(&mut self) {
+        let mut best = self.layers.clone();
+        let mut best_crossings = self.crossings();
+
+        for sweep in 0..ORDERING_SWEEPS {
+            let downwards = sweep % 2 == 0;
+            let order: Vec<usize> = if downwards {
+                (1..self.layers.len()).collect()
+            } else {
+                (0..self.layers.len().saturating_sub(1)).rev().collect()
+            };
+
+            for l in order {
+                let fixed = if downwards { l - 1 } else { l + 1 };
+                let mut position = vec![0.0; self.ups.len()];
+                for (i, &v) in self.layers[fixed].iter().enumerate() {
+                    position[v] = i as f64;
+                }
+
+                let neighbours = if downwards { &self.ups } else { &self.downs };
+                let mut keyed: Vec<(f64, usize)> = self.layers[l].iter().enumerate().map(|(i, &v)| {
+                    let near = &neighbours[v];
+                    if near.is_empty() {
+                        // nothing to line up with, so stay put
+                        (i as f64, v)
+                    } else {
+                        (near.iter().map(|&w| position[w]).sum::<f64>() / near.len() as f64, v)
+                    }
+                }).collect();
+                keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                self.layers[l] = keyed.into_iter().map(|(_, v)| v).collect();
+            }
+
+            let crossings = self.crossings();
+            if crossings < best_crossings {
+                best = self.layers.clone();
+                best_crossings = crossings;
+            }
+        }
+
+        self.layers = best;
+    }
+
+    /// Returns the position of every vertex along its layer, keeping the order
+    /// within each layer and at least `spacing` between neighbours.
+    fn assign_coordinates(&self, spacing: f64) -> Vec<f64> {
+        let mut along = vec![0.0; self.ups.len()];
+        for layer in self.layers.iter() {
+            let offset = (layer.len() as f64 - 1.0) * spacing / 2.0;
+            for (i, &v) in layer.iter().enumerate() {
+                along[v] = i as f64 * spacing - offset;
+            }
+        }
+
+        for _ in 0..STRAIGHTENING_PASSES {
+            for layer in self.layers.iter() {
+                let wanted: Vec<f64> = layer.iter().map(|&v| {
+                    let near: Vec<usize> = self.ups[v].iter().chain(self.downs[v].iter()).cloned().collect();
+                    if near.is_empty() {
+                        along[v]
+                    } else {
+                        near.iter().map(|&w| along[w]).sum::<f64>() / near.len() as f64
+                    }
+                }).collect();
+
+                // Push overlapping vertices apart to the right, then to the left,
+                // and meet in the middle. Both keep the spacing, so the average does too.
+                let mut rightwards = wanted.clone();
+                for i in 1..rightwards.len() {
+                    rightwards[i] = rightwards[i].max(rightwards[i - 1] + spacing);
+                }
+                let mut leftwards = wanted;
+                for i in (0..leftwards.len().saturating_sub(1)).rev() {
+                    leftwards[i] = leftwards[i].min(leftwards[i + 1] - spacing);
+                }
+                for (i, &v) in layer.iter().enumerate() {
+                    along[v] = (rightwards[i] + leftwards[i]) / 2.0;
+                }
+            }
+        }
+
+        along
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::VertexId;
+
+    fn bounds() -> Bounds {
+        Bounds::new(-500.0, 500.0, -400.0, 400.0)
+    }
+
+    fn chain(graph: &mut Graph, n: usize) -> Vec<VertexId> {
+        let ids: Vec<VertexId> = (0..n).map(|i| graph.add_vertex(i.to_string(), [0.0, 0.0])).collect();
+        for pair in ids.windows(2) {
+            graph.add_edge(pair[0], pair[1]);
+        }
+        ids
+    }
+
+    fn position(graph: &Graph, id: VertexId) -> [f64; 2] {
+        graph.vertex(id).unwrap().position
+    }
+
+    #[test]
+    fn edges_point_the_layout_direction() {
+        let mut graph = Graph::new();
+        let ids = chain(&mut graph, 4);
+        let extra = graph.add_vertex("x".to_string(), [0.0, 0.0]);
+        graph.add_edge(ids[0], extra);
+        graph.add_edge(extra, ids[3]);
+
+        LayeredLayout::new(20.0, bounds(), Direction::TopDown).run(&mut graph);
+        for (_, e) in graph.edges() {
+            assert!(position(&graph, e.source())[1] > position(&graph, e.target())[1]);
+        }
+
+        LayeredLayout::new(20.0, bounds(), Direction::LeftRight).run(&mut graph);
+        for (_, e) in graph.edges() {
+            assert!(position(&graph, e.source())[0] < position(&graph, e.target())[0]);
+        }
+    }
+
+    #[test]
+    fn layers_are_spaced_evenly() {
+        let mut graph = Graph::new();
+        let ids = chain(&mut graph, 3);
+        let layout = LayeredLayout::new(10.0, bounds(), Direction::TopDown);
+        layout.run(&mut graph);
+        let ys: Vec<f64> = ids.iter().map(|&id| position(&graph, id)[1]).collect();
+        assert!((ys[0] - ys[1] - layout.layer_spacing).abs() < 1e-9);
+        assert!((ys[1] - ys[2] - layout.layer_spacing).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cycles_and_self_loops_are_laid_out() {
+        let mut graph = Graph::new();
+        let ids = chain(&mut graph, 3);
+        graph.add_edge(ids[2], ids[0]);
+        graph.add_edge(ids[1], ids[1]);
+        LayeredLayout::new(20.0, bounds(), Direction::TopDown).run(&mut graph);
+
+        let mut ys: Vec<f64> = ids.iter().map(|&id| position(&graph, id)[1]).collect();
+        ys.dedup();
+        assert_eq!(ys.len(), 3, "each vertex of the cycle gets a layer of its own");
+    }
+
+    #[test]
+    fn vertices_in_a_layer_dont_overlap() {
+        let mut graph = Graph::new();
+        let root = graph.add_vertex("root".to_string(), [0.0, 0.0]);
+        for i in 0..5 {
+            let child = graph.add_vertex(i.to_string(), [0.0, 0.0]);
+            graph.add_edge(root, child);
+        }
+        let layout = LayeredLayout::new(10.0, bounds(), Direction::TopDown);
+        layout.run(&mut graph);
+
+        let mut xs: Vec<f64> = graph.vertices().filter(|&(id, _)| id != root).map(|(_, v)| v.position[0]).collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for pair in xs.windows(2) {
+            assert!(pair[1] - pair[0] >= layout.vertex_spacing - 1e-9);
+        }
+    }
+
+    #[test]
+    fn everything_fits_in_the_bounds() {
+        let mut graph = Graph::new();
+        chain(&mut graph, 40);
+        let small = Bounds::new(0.0, 200.0, 0.0, 100.0);
+        LayeredLayout::new(10.0, small, Direction::TopDown).run(&mut graph);
+        for (_, v) in graph.vertices() {
+            assert!(small.left + 10.0 <= v.position[0] && v.position[0] <= small.right - 10.0);
+            assert!(small.bottom + 10.0 <= v.position[1] && v.position[1] <= small.top - 10.0);
+        }
+    }
+}
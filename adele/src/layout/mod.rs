@@ -0,0 +1,47 @@
+//! Automatic placement of vertices.
+
+pub mod force;
+pub mod layered;
+
+
+/// The area vertices are allowed in, in the same coordinates as `Vertex::position`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub left: f64,
+    pub right: f64,
+    pub bottom: f64,
+    pub top: f64
+}
+
+impl Bounds {
+    pub fn new(left: f64, right: f64, bottom: f64, top: f64) -> Self {
+        Bounds { left, right, bottom, top }
+    }
+
+    pub fn width(&self) -> f64 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f64 {
+        self.top - self.bottom
+    }
+
+    pub fn center(&self) -> [f64; 2] {
+        [(self.left + self.right) / 2.0, (self.bottom + self.top) / 2.0]
+    }
+
+    /// Moves a point inside the bounds shrunk by `padding` on every side.
+    /// When the bounds are too small for the padding the point ends up in the middle.
+    pub fn clamp(&self, p: [f64; 2], padding: f64) -> [f64; 2] {
+        fn clamp_1d(value: f64, low: f64, high: f64) -> f64 {
+            if low > high {
+                (low + high) / 2.0
+            } else {
+                value.max(low).min(high)
+            }
+        }
+
+        [clamp_1d(p[0], self.left + padding, self.right - padding),
+         clamp_1d(p[1], self.bottom + padding, self.top - padding)]
+    }
+}
@@ -0,0 +1,86 @@
+//! Reading text a character at a time while keeping track of the line and
+//! column, which the lexers of the text format, DOT and the type language share.
+
+use text::ParseError;
+
+
+/// A place in the text. Lines and columns count from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize
+}
+
+impl Pos {
+    pub fn error<S: Into<String>>(self, message: S) -> ParseError {
+        ParseError { line: self.line, column: self.column, message: message.into() }
+    }
+}
+
+/// The characters of a text and how far into them a lexer has got.
+pub struct Cursor {
+    chars: Vec<char>,
+    index: usize,
+    /// Where the next character is.
+    pub pos: Pos
+}
+
+impl Cursor {
+    pub fn new(text: &str) -> Self {
+        Cursor { chars: text.chars().collect(), index: 0, pos: Pos { line: 1, column: 1 } }
+    }
+
+    /// The character `ahead` places after the next one, so `peek(0)` is the next one.
+    pub fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.index + ahead).cloned()
+    }
+
+    /// Moves past the next character and returns it.
+    pub fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0);
+        if let Some(c) = c {
+            self.index += 1;
+            if c == '\n' {
+                self.pos.line += 1;
+                self.pos.column = 1;
+            } else {
+                self.pos.column += 1;
+            }
+        }
+        c
+    }
+
+    /// Where the cursor is now, to go back to with `restore`.
+    pub fn save(&self) -> (usize, Pos) {
+        (self.index, self.pos)
+    }
+
+    pub fn restore(&mut self, saved: (usize, Pos)) {
+        self.index = saved.0;
+        self.pos = saved.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_columns() {
+        let mut cursor = Cursor::new("ab\nc");
+        assert_eq!(cursor.peek(1), Some('b'));
+        assert_eq!(cursor.bump(), Some('a'));
+        assert_eq!(cursor.pos, Pos { line: 1, column: 2 });
+        let saved = cursor.save();
+        cursor.bump();
+        cursor.bump();
+        assert_eq!(cursor.pos, Pos { line: 2, column: 1 });
+        assert_eq!(cursor.bump(), Some('c'));
+        assert_eq!(cursor.bump(), None);
+        assert_eq!(cursor.pos, Pos { line: 2, column: 2 });
+
+        cursor.restore(saved);
+        assert_eq!(cursor.peek(0), Some('b'));
+        assert_eq!(cursor.pos.error("oops"), ParseError { line: 1, column: 2, message: "oops".to_string() });
+    }
+}
@@ -0,0 +1,16 @@
+//! The graph model behind adele-gui and everything that works on it without a window:
+//! the text format, document files, undo history, layouts, analysis, exports,
+//! the type language and a spatial index.
+
+pub mod analysis;
+pub mod document;
+pub mod dot;
+pub mod geometry;
+pub mod graph;
+pub mod history;
+pub mod layout;
+mod lexer;
+pub mod spatial;
+pub mod svg;
+pub mod text;
+pub mod types;
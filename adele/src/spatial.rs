@@ -0,0 +1,360 @@
+//! A grid over the plane for finding the vertices and edges near a point or
+//! inside an area without looking at every one of them.
+//!
+//! The index follows a graph by comparing it against what was indexed last,
+//! so it doesn't matter how the graph was changed in between.
+
+use std::collections::{BTreeSet, HashMap};
+
+use geometry::Point;
+use graph::{EdgeId, Graph, VertexId};
+use layout::Bounds;
+
+
+type Cell = (i64, i64);
+
+// Edges covering more cells than this are kept aside and checked on every query,
+// so that a few long edges don't fill up the whole grid.
+const MAX_EDGE_CELLS: f64 = 64.0;
+
+#[derive(Clone, Debug)]
+struct IndexedEdge {
+    source: VertexId,
+    target: VertexId,
+    // The lowest and highest cell the edge is in, or `None` for a long edge.
+    cells: Option<(Cell, Cell)>
+}
+
+#[derive(Clone, Debug)]
+pub struct SpatialIndex {
+    cell_size: f64,
+    radius: f64,
+    vertices: HashMap<VertexId, Point>,
+    edges: HashMap<EdgeId, IndexedEdge>,
+    vertex_cells: HashMap<Cell, Vec<VertexId>>,
+    edge_cells: HashMap<Cell, Vec<EdgeId>>,
+    long_edges: BTreeSet<EdgeId>
+}
+
+impl SpatialIndex {
+    /// An empty index for vertices of the given radius. Cells of a few vertex
+    /// diameters work well.
+    pub fn new(cell_size: f64, radius: f64) -> Self {
+        SpatialIndex {
+            cell_size,
+            radius,
+            vertices: HashMap::new(),
+            edges: HashMap::new(),
+            vertex_cells: HashMap::new(),
+            edge_cells: HashMap::new(),
+            long_edges: BTreeSet::new()
+        }
+    }
+
+    /// Starts over when the vertices are drawn at another size.
+    pub fn set_radius(&mut self, radius: f64) {
+        if radius != self.radius {
+            *self = SpatialIndex::new(self.cell_size, radius);
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// Catches up with `graph`, only reindexing vertices that moved, appeared or
+    /// disappeared since the last update, and the edges that touch them.
+    /// Finding those still means looking at every vertex and edge, so this is
+    /// best left out when the graph is known not to have changed.
+    pub fn update(&mut self, graph: &Graph) {
+        let mut stale_edges: BTreeSet<EdgeId> = BTreeSet::new();
+
+        let gone: Vec<VertexId> = self.vertices.keys().cloned().filter(|&id| graph.vertex(id).is_none()).collect();
+        for id in gone {
+            let position = self.vertices.remove(&id).unwrap();
+            let cell = self.cell_of(position);
+            remove_from(&mut self.vertex_cells, cell, id);
+        }
+
+        for (id, v) in graph.vertices() {
+            match self.vertices.get(&id).cloned() {
+                Some(position) if position == v.position => continue,
+                Some(position) => {
+                    let cell = self.cell_of(position);
+                    remove_from(&mut self.vertex_cells, cell, id);
+                },
+                None => ()
+            }
+            self.vertices.insert(id, v.position);
+            let cell = self.cell_of(v.position);
+            self.vertex_cells.entry(cell).or_default().push(id);
+            stale_edges.extend(v.outs().iter().chain(v.ins().iter()).cloned());
+        }
+
+        // Edges that are gone, or came back between other vertices under the same ID.
+        let changed: Vec<EdgeId> = self.edges.iter()
+            .filter(|&(&id, e)| graph.edge(id).is_none_or(|edge| (edge.source(), edge.target()) != (e.source, e.target)))
+            .map(|(&id, _)| id)
+            .collect();
+        stale_edges.extend(changed);
+        stale_edges.extend(graph.edges().map(|(id, _)| id).filter(|id| !self.edges.contains_key(id)));
+
+        for id in stale_edges {
+            if let Some(indexed) = self.edges.remove(&id) {
+                self.unindex_edge(id, &indexed);
+            }
+            if let Some(edge) = graph.edge(id) {
+                self.index_edge(graph, id, edge.source(), edge.target());
+            }
+        }
+    }
+
+    /// Vertices whose circle might reach into `area`.
+    pub fn vertices_in(&self, area: &Bounds) -> Vec<VertexId> {
+        let r = self.radius;
+        let (low, high) = self.cells_of(&Bounds::new(area.left - r, area.right + r, area.bottom - r, area.top + r));
+        let mut found = vec![];
+        collect(&self.vertex_cells, low, high, &mut found);
+        found.retain(|id| {
+            let p = self.vertices[id];
+            p[0] + r >= area.left && p[0] - r <= area.right &&
+            p[1] + r >= area.bottom && p[1] - r <= area.top
+        });
+        found.sort();
+        found
+    }
+
+    /// Vertices whose circle might hold `p`.
+    pub fn vertices_at(&self, p: Point) -> Vec<VertexId> {
+        self.vertices_in(&Bounds::new(p[0], p[0], p[1], p[1]))
+    }
+
+    /// Edges whose arrow might reach into `area`, labels aside.
+    pub fn edges_in(&self, area: &Bounds) -> Vec<EdgeId> {
+        let (low, high) = self.cells_of(area);
+        let mut found: Vec<EdgeId> = self.long_edges.iter().cloned().collect();
+        collect(&self.edge_cells, low, high, &mut found);
+        // an edge is in every cell it covers
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    fn cell_of(&self, p: Point) -> Cell {
+        ((p[0] / self.cell_size).floor() as i64, (p[1] / self.cell_size).floor() as i64)
+    }
+
+    fn cells_of(&self, area: &Bounds) -> (Cell, Cell) {
+        (self.cell_of([area.left, area.bottom]), self.cell_of([area.right, area.top]))
+    }
+
+    // Every shape an edge is drawn as stays within two radii of its ends:
+    // loops go up to the top of a circle on top of the vertex, and curves
+    // bow out by half a radius.
+    fn edge_area(&self, source: Point, target: Point) -> Bounds {
+        let reach = 2.0 * self.radius;
+        Bounds::new(source[0].min(target[0]) - reach, source[0].max(target[0]) + reach,
+                    source[1].min(target[1]) - reach, source[1].max(target[1]) + reach)
+    }
+
+    fn index_edge(&mut self, graph: &Graph, id: EdgeId, source: VertexId, target: VertexId) {
+        let area = self.edge_area(graph.vertex(source).unwrap().position, graph.vertex(target).unwrap().position);
+        let (low, high) = self.cells_of(&area);
+        let cells = if cell_count(low, high) > MAX_EDGE_CELLS {
+            self.long_edges.insert(id);
+            None
+        } else {
+            for x in low.0..high.0 + 1 {
+                for y in low.1..high.1 + 1 {
+                    self.edge_cells.entry((x, y)).or_default().push(id);
+                }
+            }
+            Some((low, high))
+        };
+        self.edges.insert(id, IndexedEdge { source, target, cells });
+    }
+
+    fn unindex_edge(&mut self, id: EdgeId, indexed: &IndexedEdge) {
+        match indexed.cells {
+            Some((low, high)) => for x in low.0..high.0 + 1 {
+                for y in low.1..high.1 + 1 {
+                    remove_from(&mut self.edge_cells, (x, y), id);
+                }
+            },
+            None => { self.long_edges.remove(&id); }
+        }
+    }
+}
+
+// Counted in floating point, since an area reaching infinity covers more cells than fit in an `i64`.
+fn cell_count(low: Cell, high: Cell) -> f64 {
+    (high.0 as f64 - low.0 as f64 + 1.0) * (high.1 as f64 - low.1 as f64 + 1.0)
+}
+
+/// Adds what's in the cells from `low` to `high` to `out`.
+fn collect<T: Copy>(cells: &HashMap<Cell, Vec<T>>, low: Cell, high: Cell, out: &mut Vec<T>) {
+    if cell_count(low, high) > cells.len() as f64 {
+        // it's quicker to go through the cells that have something in them
+        for (&(x, y), items) in cells.iter() {
+            if low.0 <= x && x <= high.0 && low.1 <= y && y <= high.1 {
+                out.extend(items.iter().cloned());
+            }
+        }
+    } else {
+        for x in low.0..high.0 + 1 {
+            for y in low.1..high.1 + 1 {
+                if let Some(items) = cells.get(&(x, y)) {
+                    out.extend(items.iter().cloned());
+                }
+            }
+        }
+    }
+}
+
+fn remove_from<T: PartialEq>(cells: &mut HashMap<Cell, Vec<T>>, cell: Cell, item: T) {
+    let empty = match cells.get_mut(&cell) {
+        Some(items) => {
+            items.retain(|i| *i != item);
+            items.is_empty()
+        },
+        None => false
+    };
+    if empty {
+        cells.remove(&cell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry;
+
+    const RADIUS: f64 = 10.0;
+
+    // A small deterministic generator, so failures can be reproduced.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn point(&mut self) -> Point {
+            [self.next() * 2000.0 - 1000.0, self.next() * 2000.0 - 1000.0]
+        }
+    }
+
+    fn overlaps(a: &Bounds, b: &Bounds) -> bool {
+        a.left <= b.right && b.left <= a.right && a.bottom <= b.top && b.bottom <= a.top
+    }
+
+    /// Checks the index against looking at every vertex and edge.
+    fn check(index: &SpatialIndex, graph: &Graph, area: &Bounds) {
+        let found = index.vertices_in(area);
+        for (id, v) in graph.vertices() {
+            let p = v.position;
+            let circle = Bounds::new(p[0] - RADIUS, p[0] + RADIUS, p[1] - RADIUS, p[1] + RADIUS);
+            assert_eq!(found.contains(&id), overlaps(&circle, area), "vertex {:?}", id);
+        }
+
+        let found = index.edges_in(area);
+        for (id, _) in graph.edges() {
+            let arrow = geometry::edge_arrow(graph, id, RADIUS, 3.0, 2.0).unwrap();
+            let touches = arrow.path.iter().chain(arrow.head.iter()).any(|p| {
+                area.left <= p[0] && p[0] <= area.right && area.bottom <= p[1] && p[1] <= area.top
+            });
+            if touches {
+                assert!(found.contains(&id), "edge {:?}", id);
+            }
+        }
+        for id in found {
+            assert!(graph.edge(id).is_some(), "removed edge {:?} is still indexed", id);
+        }
+    }
+
+    fn random_graph(random: &mut Random, vertices: usize, edges: usize) -> Graph {
+        let mut graph = Graph::new();
+        for i in 0..vertices {
+            graph.add_vertex(i.to_string(), random.point());
+        }
+        let ids = graph.vertex_ids();
+        for _ in 0..edges {
+            let pick = |r: f64| ids[(r * ids.len() as f64) as usize];
+            let (s, t) = (pick(random.next()), pick(random.next()));
+            graph.add_edge(s, t);
+        }
+        graph
+    }
+
+    #[test]
+    fn finds_what_a_full_scan_finds() {
+        let mut random = Random(1);
+        let mut graph = random_graph(&mut random, 300, 400);
+        let mut index = SpatialIndex::new(50.0, RADIUS);
+
+        for _ in 0..4 {
+            index.update(&graph);
+            for _ in 0..20 {
+                let (a, b) = (random.point(), random.point());
+                let area = Bounds::new(a[0].min(b[0]), a[0].max(b[0]), a[1].min(b[1]), a[1].max(b[1]));
+                check(&index, &graph, &area);
+                check(&index, &graph, &Bounds::new(a[0], a[0], a[1], a[1]));
+            }
+
+            // move, remove and add some of everything
+            let ids = graph.vertex_ids();
+            for &id in ids.iter().step_by(7) {
+                graph.vertex_mut(id).unwrap().position = random.point();
+            }
+            for &id in ids.iter().step_by(11) {
+                graph.remove_vertex(id);
+            }
+            let edges: Vec<EdgeId> = graph.edges().map(|(id, _)| id).collect();
+            for &id in edges.iter().step_by(5) {
+                graph.remove_edge(id);
+            }
+            let added = graph.add_graph(&random_graph(&mut random, 20, 20), [0.0, 0.0]);
+            graph.add_edge(added[0], ids[1]);
+        }
+    }
+
+    #[test]
+    fn vertices_at_a_point() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_string(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_string(), [15.0, 0.0]);
+        graph.add_vertex("c".to_string(), [300.0, 300.0]);
+        let mut index = SpatialIndex::new(50.0, RADIUS);
+        index.update(&graph);
+        assert_eq!(index.vertices_at([7.0, 0.0]), vec![a, b]);
+        assert_eq!(index.vertices_at([-200.0, 0.0]), vec![]);
+    }
+
+    #[test]
+    fn long_edges_are_always_found() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_string(), [-1e6, 0.0]);
+        let b = graph.add_vertex("b".to_string(), [1e6, 0.0]);
+        let e = graph.add_edge(a, b).unwrap();
+        let mut index = SpatialIndex::new(50.0, RADIUS);
+        index.update(&graph);
+        assert_eq!(index.edges_in(&Bounds::new(0.0, 1.0, 0.0, 1.0)), vec![e]);
+
+        graph.remove_edge(e);
+        index.update(&graph);
+        assert_eq!(index.edges_in(&Bounds::new(0.0, 1.0, 0.0, 1.0)), vec![]);
+    }
+
+    #[test]
+    fn a_new_radius_starts_over() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_string(), [0.0, 0.0]);
+        let mut index = SpatialIndex::new(50.0, RADIUS);
+        index.update(&graph);
+        assert_eq!(index.vertices_at([30.0, 0.0]), vec![]);
+        index.set_radius(40.0);
+        index.update(&graph);
+        assert_eq!(index.vertices_at([30.0, 0.0]), vec![a]);
+    }
+}
@@ -0,0 +1,285 @@
+//! Writing a graph as an SVG picture, drawn the same way as in the window:
+//! circles for vertices with their label in the middle, and arrows for edges
+//! with their label beside them.
+//!
+//! The picture is cropped to the graph. Graph coordinates have y pointing up,
+//! so they're flipped on the way out.
+
+use std::collections::BTreeMap;
+
+use geometry::{self, Arrow, Point};
+use graph::{EdgeId, Graph};
+
+
+/// Sizes and colors of the picture. Colors are red, green, blue and alpha between 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgStyle {
+    pub vertex_radius: f64,
+    pub vertex_outline_color: [f32; 4],
+    pub vertex_fill_color: [f32; 4],
+    pub edge_color: [f32; 4],
+    pub arrow_base: f64,
+    pub arrow_height: f64,
+    /// Thickness of vertex outlines and edge lines.
+    pub line_thickness: f64,
+    pub label_color: [f32; 4],
+    pub font_size: f64,
+    pub font_family: String,
+    /// Empty space around the graph.
+    pub margin: f64
+}
+
+impl Default for SvgStyle {
+    fn default() -> Self {
+        SvgStyle {
+            vertex_radius: 35.0,
+            vertex_outline_color: [0.2, 0.2, 0.2, 1.0],
+            vertex_fill_color: [0.99, 0.99, 1.0, 1.0],
+            edge_color: [0.2, 0.2, 0.2, 1.0],
+            arrow_base: 15.0,
+            arrow_height: 10.0,
+            line_thickness: 2.0,
+            label_color: [0.0, 0.0, 0.0, 1.0],
+            font_size: 12.0,
+            font_family: "Hack, monospace".to_string(),
+            margin: 10.0
+        }
+    }
+}
+
+impl SvgStyle {
+    // Roughly how wide a label is, going by the proportions of a monospace font.
+    fn label_width(&self, label: &str) -> f64 {
+        self.font_size * 0.692 * label.chars().count() as f64
+    }
+}
+
+pub fn graph_to_svg(graph: &Graph, style: &SvgStyle) -> String {
+    let r = style.vertex_radius;
+    let arrows: BTreeMap<EdgeId, Arrow> = graph.edges()
+        .filter_map(|(id, _)| geometry::edge_arrow(graph, id, r, style.arrow_base, style.arrow_height).map(|a| (id, a)))
+        .filter(|(_, arrow)| arrow.is_finite())
+        .collect();
+
+    let mut left = f64::INFINITY;
+    let mut right = f64::NEG_INFINITY;
+    let mut bottom = f64::INFINITY;
+    let mut top = f64::NEG_INFINITY;
+    let mut include = |p: Point, half_width: f64, half_height: f64| {
+        left = left.min(p[0] - half_width);
+        right = right.max(p[0] + half_width);
+        bottom = bottom.min(p[1] - half_height);
+        top = top.max(p[1] + half_height);
+    };
+    for (_, v) in graph.vertices() {
+        include(v.position, r.max(style.label_width(&v.label) / 2.0), r);
+    }
+    for (&id, arrow) in arrows.iter() {
+        for &p in arrow.path.iter().chain(arrow.head.iter()) {
+            include(p, 0.0, 0.0);
+        }
+        let label = &graph.edge(id).unwrap().label;
+        if !label.is_empty() {
+            include(arrow.label_position(edge_label_distance(style)),
+                    style.label_width(label) / 2.0, style.font_size / 2.0);
+        }
+    }
+    if graph.vertex_count() == 0 {
+        left = 0.0;
+        right = 0.0;
+        bottom = 0.0;
+        top = 0.0;
+    }
+
+    let margin = style.margin + style.line_thickness;
+    let width = right - left + 2.0 * margin;
+    let height = top - bottom + 2.0 * margin;
+    let to_svg = |p: Point| [p[0] - left + margin, top - p[1] + margin];
+    let points = |points: &[Point]| -> String {
+        let points: Vec<String> = points.iter().map(|&p| {
+            let p = to_svg(p);
+            format!("{},{}", number(p[0]), number(p[1]))
+        }).collect();
+        points.join(" ")
+    };
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+                          number(width), number(height), number(width), number(height)));
+
+    // Same order as the window: each vertex's outgoing edges, then the vertex on top.
+    for id in graph.vertex_ids() {
+        let v = graph.vertex(id).unwrap();
+        for e in v.outs().iter() {
+            let arrow = match arrows.get(e) {
+                Some(arrow) => arrow,
+                None => continue
+            };
+
+            out.push_str(&format!("  <polyline points=\"{}\" fill=\"none\" {} stroke-width=\"{}\"/>\n",
+                                  points(&arrow.path), paint("stroke", style.edge_color), number(style.line_thickness)));
+            out.push_str(&format!("  <polygon points=\"{}\" {}/>\n",
+                                  points(&arrow.head), paint("fill", style.edge_color)));
+
+            let edge = graph.edge(*e).unwrap();
+            if !edge.label.is_empty() {
+                let p = to_svg(arrow.label_position(edge_label_distance(style)));
+                out.push_str(&text(p, &edge.label, style));
+            }
+        }
+
+        let c = to_svg(v.position);
+        out.push_str(&format!("  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>\n",
+                              number(c[0]), number(c[1]), number(r), paint("fill", style.vertex_fill_color)));
+        out.push_str(&format!("  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" {} stroke-width=\"{}\"/>\n",
+                              number(c[0]), number(c[1]), number(r),
+                              paint("stroke", style.vertex_outline_color), number(style.line_thickness)));
+        out.push_str(&text(c, &v.label, style));
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+/// How far edge labels are from their edge, enough to keep a line of text clear of it.
+fn edge_label_distance(style: &SvgStyle) -> f64 {
+    1.25 * style.font_size
+}
+
+fn text(p: Point, label: &str, style: &SvgStyle) -> String {
+    format!("  <text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" {}>{}</text>\n",
+            number(p[0]), number(p[1]), escape(&style.font_family), number(style.font_size),
+            paint("fill", style.label_color), escape(label))
+}
+
+// Two decimals are plenty for a picture, and keep the file readable.
+fn number(x: f64) -> String {
+    let s = format!("{:.2}", x);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+fn paint(attribute: &str, color: [f32; 4]) -> String {
+    let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let mut s = format!("{}=\"#{:02x}{:02x}{:02x}\"", attribute, byte(color[0]), byte(color[1]), byte(color[2]));
+    if color[3] < 1.0 {
+        s.push_str(&format!(" {}-opacity=\"{}\"", attribute, number(color[3].max(0.0) as f64)));
+    }
+    s
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Values of `name="..."` in every element of the given kind.
+    fn attributes<'a>(svg: &'a str, element: &str, name: &str) -> Vec<&'a str> {
+        let start = format!("<{} ", element);
+        let key = format!(" {}=\"", name);
+        svg.lines()
+            .filter(|line| line.trim_start().starts_with(&start))
+            .filter_map(|line| {
+                let from = line.find(&key)? + key.len();
+                let to = from + line[from..].find('"')?;
+                Some(&line[from..to])
+            })
+            .collect()
+    }
+
+    fn numbers(text: &str) -> Vec<f64> {
+        text.split([' ', ',']).map(|n| n.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a<b & \"c\"".to_owned(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_owned(), [100.0, 0.0]);
+        let e = graph.add_edge(a, b).unwrap();
+        graph.edge_mut(e).unwrap().label = "x>y".to_owned();
+        let svg = graph_to_svg(&graph, &SvgStyle::default());
+        assert!(svg.contains(">a&lt;b &amp; &quot;c&quot;</text>"));
+        assert!(svg.contains(">x&gt;y</text>"));
+        assert!(!svg.contains("a<b"));
+    }
+
+    #[test]
+    fn loops_and_curves_are_drawn_with_more_points() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_owned(), [0.0, 0.0]);
+        let b = graph.add_vertex("b".to_owned(), [200.0, 0.0]);
+        let c = graph.add_vertex("c".to_owned(), [0.0, 200.0]);
+        graph.add_edge(a, a).unwrap();
+        graph.add_edge(a, b).unwrap();
+        graph.add_edge(b, a).unwrap();
+        graph.add_edge(a, c).unwrap();
+        let svg = graph_to_svg(&graph, &SvgStyle::default());
+
+        // a's edges come first, in the order they were added, then b's
+        let lines: Vec<usize> = attributes(&svg, "polyline", "points").iter()
+            .map(|points| numbers(points).len() / 2)
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0] > 2);
+        assert!(lines[1] > 2);
+        assert_eq!(lines[2], 2);
+        assert!(lines[3] > 2);
+        assert_eq!(attributes(&svg, "polygon", "points").len(), 4);
+    }
+
+    #[test]
+    fn the_picture_covers_every_vertex() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("a".to_owned(), [-300.0, 50.0]);
+        let b = graph.add_vertex("a rather long label".to_owned(), [120.0, -80.0]);
+        graph.add_vertex("c".to_owned(), [40.0, 400.0]);
+        graph.add_edge(a, a).unwrap();
+        graph.add_edge(a, b).unwrap();
+        let style = SvgStyle::default();
+        let svg = graph_to_svg(&graph, &style);
+
+        let view_box = numbers(attributes(&svg, "svg", "viewBox")[0]);
+        assert_eq!(&view_box[..2], &[0.0, 0.0]);
+        let (width, height) = (view_box[2], view_box[3]);
+        let inside = |x: f64, y: f64| x >= 0.0 && x <= width && y >= 0.0 && y <= height;
+
+        let xs = attributes(&svg, "circle", "cx");
+        let ys = attributes(&svg, "circle", "cy");
+        assert_eq!(xs.len(), 6);
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (x, y): (f64, f64) = (x.parse().unwrap(), y.parse().unwrap());
+            assert!(inside(x - style.vertex_radius, y - style.vertex_radius));
+            assert!(inside(x + style.vertex_radius, y + style.vertex_radius));
+        }
+        for points in attributes(&svg, "polyline", "points").into_iter()
+            .chain(attributes(&svg, "polygon", "points")) {
+            for p in numbers(points).chunks(2) {
+                assert!(inside(p[0], p[1]));
+            }
+        }
+        // y points down in the picture
+        assert!(ys[4].parse::<f64>().unwrap() < ys[0].parse::<f64>().unwrap());
+    }
+
+    #[test]
+    fn empty_graphs_are_just_the_margin() {
+        let svg = graph_to_svg(&Graph::new(), &SvgStyle::default());
+        assert_eq!(attributes(&svg, "svg", "viewBox"), vec!["0 0 24 24"]);
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
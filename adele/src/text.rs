@@ -0,0 +1,532 @@
+//! The textual graph format.
+//!
+//! A document is a list of statements, each ending in a semicolon:
+//!
+//! ```text
+//! Hello world!;
+//! Holy smokes!;
+//!
+//! Holy smokes! => Hello world! : says;
+//! ```
+//!
+//! A statement with a single label declares a vertex, and `A => B;` connects
+//! two vertices. A connection can be labelled too, as in `A => B : label;`.
+//! Labels are written as-is with surrounding whitespace trimmed, or in double
+//! quotes when they contain `;`, `"`, `=>`, `:`, `#` or line breaks. Only the target
+//! of a connection actually ends at a `:`, but quoting is harmless elsewhere.
+//!
+//! Vertices that share a label are told apart by a name after a `#`, made of
+//! letters, digits and `_`:
+//!
+//! ```text
+//! new node #1;
+//! new node #2;
+//!
+//! new node #1 => new node #2;
+//! ```
+//!
+//! The name only exists in the text; it isn't part of the vertex label.
+//!
+//! Parentheses have no meaning here. The type language in `types` uses them to
+//! give a vertex a type, as in `bob => alice (Person);`, where `:` would start
+//! the connection's label instead.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use analysis::first_cycle_edge;
+use graph::{Graph, VertexId};
+use lexer::{Cursor, Pos};
+
+
+/// Something in the text couldn't be turned into a graph.
+/// Lines and columns count from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Label(String),
+    // A `#` and the name after it.
+    Name(String),
+    Arrow,
+    Colon,
+    Semicolon
+}
+
+struct Lexer {
+    cursor: Cursor
+}
+
+impl Lexer {
+    fn new(text: &str) -> Self {
+        Lexer { cursor: Cursor::new(text) }
+    }
+
+    fn at_arrow(&self) -> bool {
+        self.cursor.peek(0) == Some('=') && self.cursor.peek(1) == Some('>')
+    }
+
+    /// Returns the next token and where it starts, or `None` at the end of the text.
+    fn next(&mut self) -> Result<Option<(Token, Pos)>, ParseError> {
+        self.token(false)
+    }
+
+    /// Like `next`, but a `:` ends a label and is a token of its own,
+    /// for reading the target of a connection and its label after it.
+    fn next_in_target(&mut self) -> Result<Option<(Token, Pos)>, ParseError> {
+        self.token(true)
+    }
+
+    fn token(&mut self, colon: bool) -> Result<Option<(Token, Pos)>, ParseError> {
+        while self.cursor.peek(0).is_some_and(|c| c.is_whitespace()) {
+            self.cursor.bump();
+        }
+
+        let start = self.cursor.pos;
+        match self.cursor.peek(0) {
+            None => Ok(None),
+            Some(';') => {
+                self.cursor.bump();
+                Ok(Some((Token::Semicolon, start)))
+            },
+            Some(':') if colon => {
+                self.cursor.bump();
+                Ok(Some((Token::Colon, start)))
+            },
+            Some('#') => {
+                self.cursor.bump();
+                let mut name = String::new();
+                while let Some(c) = self.cursor.peek(0).filter(|&c| c.is_alphanumeric() || c == '_') {
+                    name.push(c);
+                    self.cursor.bump();
+                }
+                if name.is_empty() {
+                    return Err(self.cursor.pos.error("expected a name after `#`"));
+                }
+                Ok(Some((Token::Name(name), start)))
+            },
+            Some('"') => {
+                self.cursor.bump();
+                self.quoted(start).map(|label| Some((Token::Label(label), start)))
+            },
+            Some(_) if self.at_arrow() => {
+                self.cursor.bump();
+                self.cursor.bump();
+                Ok(Some((Token::Arrow, start)))
+            },
+            Some(_) => {
+                let mut label = String::new();
+                while let Some(c) = self.cursor.peek(0) {
+                    if c == ';' || c == '"' || c == '#' || c == '\n' || (colon && c == ':') || self.at_arrow() {
+                        break;
+                    }
+                    label.push(c);
+                    self.cursor.bump();
+                }
+                let trimmed = label.trim_end().to_string();
+                Ok(Some((Token::Label(trimmed), start)))
+            }
+        }
+    }
+
+    /// Reads the rest of a quoted label, after its opening quote.
+    fn quoted(&mut self, start: Pos) -> Result<String, ParseError> {
+        let mut label = String::new();
+        loop {
+            let escape_pos = self.cursor.pos;
+            match self.cursor.bump() {
+                None => return Err(start.error("unterminated quoted label")),
+                Some('"') => return Ok(label),
+                Some('\\') => match self.cursor.bump() {
+                    Some('"') => label.push('"'),
+                    Some('\\') => label.push('\\'),
+                    Some('n') => label.push('\n'),
+                    Some('t') => label.push('\t'),
+                    Some(c) => return Err(escape_pos.error(format!("unknown escape `\\{}`", c))),
+                    None => return Err(start.error("unterminated quoted label"))
+                },
+                Some(c) => label.push(c)
+            }
+        }
+    }
+}
+
+/// A vertex as the text refers to it: by its label, and a name if it has one.
+struct Reference {
+    label: String,
+    name: Option<String>,
+    pos: Pos
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "{} #{}", self.label, name),
+            None => write!(f, "{}", self.label)
+        }
+    }
+}
+
+struct Connection {
+    source: Reference,
+    target: Reference,
+    label: String
+}
+
+/// Reads the target of a connection, which ends at a `:` if the connection has a label,
+/// and returns it along with the token after it.
+fn expect_target(lexer: &mut Lexer) -> Result<(Reference, Option<(Token, Pos)>), ParseError> {
+    match lexer.next_in_target()? {
+        Some((Token::Label(label), pos)) => {
+            let (name, next) = match lexer.next_in_target()? {
+                Some((Token::Name(name), _)) => (Some(name), lexer.next_in_target()?),
+                next => (None, next)
+            };
+            Ok((Reference { label, name, pos }, next))
+        },
+        Some((_, pos)) => Err(pos.error("expected a vertex label")),
+        None => Err(lexer.cursor.pos.error("expected a vertex label"))
+    }
+}
+
+fn expect_semicolon(lexer: &mut Lexer) -> Result<(), ParseError> {
+    match lexer.next()? {
+        Some((Token::Semicolon, _)) => Ok(()),
+        Some((_, pos)) => Err(pos.error("expected `;`")),
+        None => Err(lexer.cursor.pos.error("expected `;`"))
+    }
+}
+
+/// Builds a graph from text in the format `graph_to_string` produces.
+///
+/// Vertices are created in the order they are declared. A label used in a
+/// connection without being declared gets a vertex of its own, but a label
+/// declared more than once can only be used in a connection together with a name.
+/// Every vertex starts at the origin; placing them is up to the caller.
+pub fn parse_graph(text: &str) -> Result<Graph, ParseError> {
+    parse(text, false)
+}
+
+/// Like `parse_graph`, but a connection that closes a cycle is an error.
+pub fn parse_acyclic_graph(text: &str) -> Result<Graph, ParseError> {
+    parse(text, true)
+}
+
+fn parse(text: &str, acyclic: bool) -> Result<Graph, ParseError> {
+    let mut lexer = Lexer::new(text);
+    let mut declarations: Vec<Reference> = vec![];
+    let mut connections: Vec<Connection> = vec![];
+
+    while let Some((token, pos)) = lexer.next()? {
+        let label = match token {
+            Token::Label(label) => label,
+            _ => return Err(pos.error("expected a vertex label"))
+        };
+        let (name, next) = match lexer.next()? {
+            Some((Token::Name(name), _)) => (Some(name), lexer.next()?),
+            next => (None, next)
+        };
+        let reference = Reference { label, name, pos };
+
+        match next {
+            Some((Token::Semicolon, _)) => declarations.push(reference),
+            Some((Token::Arrow, _)) => {
+                let (target, next) = expect_target(&mut lexer)?;
+                let edge_label = match next {
+                    Some((Token::Semicolon, _)) => String::new(),
+                    Some((Token::Colon, _)) => {
+                        let edge_label = match lexer.next()? {
+                            Some((Token::Label(edge_label), _)) => edge_label,
+                            Some((_, pos)) => return Err(pos.error("expected a connection label")),
+                            None => return Err(lexer.cursor.pos.error("expected a connection label"))
+                        };
+                        expect_semicolon(&mut lexer)?;
+                        edge_label
+                    },
+                    Some((_, pos)) => return Err(pos.error("expected `;` or `:`")),
+                    None => return Err(lexer.cursor.pos.error("expected `;` or `:`"))
+                };
+                connections.push(Connection { source: reference, target, label: edge_label });
+            },
+            Some((_, pos)) => return Err(pos.error("expected `;` or `=>`")),
+            None => return Err(lexer.cursor.pos.error("expected `;` or `=>`"))
+        }
+    }
+
+    let mut graph = Graph::new();
+    let mut by_label: HashMap<String, Vec<(Option<String>, VertexId)>> = HashMap::new();
+    for declaration in declarations {
+        let ids = by_label.entry(declaration.label.clone()).or_default();
+        if declaration.name.is_some() && ids.iter().any(|(name, _)| *name == declaration.name) {
+            return Err(declaration.pos.error(format!("`{}` is declared more than once", declaration)));
+        }
+        let id = graph.add_vertex(declaration.label, [0.0, 0.0]);
+        ids.push((declaration.name, id));
+    }
+
+    let mut edges = vec![];
+    for connection in connections {
+        let source = resolve(&mut graph, &mut by_label, &connection.source)?;
+        let target = resolve(&mut graph, &mut by_label, &connection.target)?;
+
+        match graph.add_edge(source, target) {
+            Some(edge) => {
+                graph.edge_mut(edge).unwrap().label = connection.label;
+                edges.push((edge, connection.source, connection.target));
+            },
+            None => return Err(connection.source.pos.error(format!("duplicate connection `{} => {}`",
+                                                                   connection.source, connection.target)))
+        }
+    }
+
+    if acyclic {
+        if let Some(edge) = first_cycle_edge(&graph) {
+            let (_, ref source, ref target) = *edges.iter().find(|&&(e, _, _)| e == edge).unwrap();
+            return Err(source.pos.error(format!("`{} => {}` closes a cycle", source, target)));
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Finds the vertex a connection refers to, declaring it if nothing else has.
+/// Without a name, the label has to be unique.
+fn resolve(graph: &mut Graph, by_label: &mut HashMap<String, Vec<(Option<String>, VertexId)>>,
+           reference: &Reference) -> Result<VertexId, ParseError> {
+    let ids = by_label.entry(reference.label.clone()).or_default();
+    let found = match reference.name {
+        Some(_) => ids.iter().find(|(name, _)| *name == reference.name).map(|&(_, id)| id),
+        None if ids.len() > 1 => return Err(reference.pos.error(format!(
+            "`{}` is declared more than once, so it needs a `#` name", reference.label))),
+        None => ids.first().map(|&(_, id)| id)
+    };
+    Ok(found.unwrap_or_else(|| {
+        let id = graph.add_vertex(reference.label.clone(), [0.0, 0.0]);
+        ids.push((reference.name.clone(), id));
+        id
+    }))
+}
+
+/// Writes a label so that `parse_graph` reads it back unchanged.
+fn quote_label(label: &str) -> String {
+    let plain = !label.is_empty() &&
+        label.trim() == label &&
+        !label.contains([';', '"', ':', '#', '\n']) &&
+        !label.contains("=>");
+
+    if plain {
+        return label.to_string();
+    }
+
+    let mut quoted = "\"".to_string();
+    for c in label.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Note: The vertices are listed sorted by label, with ties kept in creation order
+// and numbered in that order.
+pub fn graph_to_string(g: &Graph) -> String {
+    fn join<'a, I>(mut input: I, separator: &str) -> String
+    where I: Iterator<Item=&'a str> {
+        let first = input.next().unwrap_or("").to_string();
+        input.fold(first, |acc, s| acc + separator + s)
+    }
+
+    let mut order = g.vertex_ids();
+    order.sort_by(|&a, &b| g.vertex(a).unwrap().label.cmp(&g.vertex(b).unwrap().label));
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (_, v) in g.vertices() {
+        *counts.entry(v.label.as_str()).or_insert(0) += 1;
+    }
+    let mut references: HashMap<VertexId, String> = HashMap::new();
+    let mut numbered: HashMap<&str, usize> = HashMap::new();
+    for &id in order.iter() {
+        let label = g.vertex(id).unwrap().label.as_str();
+        let reference = if counts[label] > 1 {
+            let n = numbered.entry(label).or_insert(0);
+            *n += 1;
+            format!("{} #{}", quote_label(label), n)
+        } else {
+            quote_label(label)
+        };
+        references.insert(id, reference);
+    }
+
+    let dec_lines: Vec<_> = order.iter().map(|&id| references[&id].clone() + ";").collect();
+    let declarations: String = join(dec_lines.iter().map(|d| d.as_str()), "\n");
+    let con_lines: Vec<_> = order.iter()
+        .flat_map(|&id| {
+            let references = &references;
+            g.vertex(id).unwrap().outs().iter().map(move |&e| {
+                let edge = g.edge(e).unwrap();
+                let left = references[&id].clone();
+                let right = &references[&edge.target()];
+                if edge.label.is_empty() {
+                    left + " => " + right + ";"
+                } else {
+                    left + " => " + right + " : " + &quote_label(&edge.label) + ";"
+                }
+            })
+        }).collect();
+    let connections = join(con_lines.iter().map(|c| c.as_str()), "\n");
+
+    declarations + "\n\n" + &connections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Labels and edges of a graph, which is all the text format keeps.
+    fn shape(graph: &Graph) -> (Vec<String>, Vec<(String, String, String)>) {
+        let label = |id| graph.vertex(id).unwrap().label.clone();
+        let vertices = graph.vertices().map(|(_, v)| v.label.clone()).collect();
+        let edges = graph.edges().map(|(_, e)| (label(e.source()), label(e.target()), e.label.clone())).collect();
+        (vertices, edges)
+    }
+
+    fn round_trip(graph: &Graph) -> Graph {
+        let text = graph_to_string(graph);
+        let parsed = parse_graph(&text).unwrap_or_else(|e| panic!("{} in\n{}", e, text));
+        assert_eq!(graph_to_string(&parsed), text);
+        parsed
+    }
+
+    #[test]
+    fn plain_labels() {
+        let graph = parse_graph("Hello world!;\nHoly smokes!;\n\nHoly smokes! => Hello world!;").unwrap();
+        assert_eq!(shape(&graph), (vec!["Hello world!".to_string(), "Holy smokes!".to_string()],
+                                   vec![("Holy smokes!".to_string(), "Hello world!".to_string(), String::new())]));
+        round_trip(&graph);
+    }
+
+    #[test]
+    fn undeclared_labels_get_vertices() {
+        let graph = parse_graph("a => b; b => c;").unwrap();
+        assert_eq!(shape(&graph).0, vec!["a", "b", "c"]);
+        assert_eq!(graph.edge_count(), 2);
+    }
+
+    #[test]
+    fn quoting() {
+        let mut graph = Graph::new();
+        let labels = ["semi;colon", "\"quoted\"", "#hash", "a => b", "key: value", " padded ", "", "two\nlines",
+                      "back\\slash", "tab\there"];
+        let ids: Vec<VertexId> = labels.iter().map(|l| graph.add_vertex(l.to_string(), [0.0, 0.0])).collect();
+        for pair in ids.windows(2) {
+            graph.add_edge(pair[0], pair[1]);
+        }
+        let parsed = round_trip(&graph);
+        assert_eq!(shape(&parsed).0.len(), labels.len());
+        for label in labels.iter() {
+            assert!(shape(&parsed).0.contains(&label.to_string()), "lost {:?}", label);
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let graph = parse_graph(r#""a\"b\\c\nd\te";"#).unwrap();
+        assert_eq!(shape(&graph).0, vec!["a\"b\\c\nd\te"]);
+        assert_eq!(parse_graph(r#""\q";"#).unwrap_err(),
+                   ParseError { line: 1, column: 2, message: "unknown escape `\\q`".to_string() });
+    }
+
+    #[test]
+    fn edge_labels() {
+        let graph = parse_graph("a => b : likes; b => c : \"says: hi;\";").unwrap();
+        let edges = shape(&graph).1;
+        assert_eq!(edges[0], ("a".to_string(), "b".to_string(), "likes".to_string()));
+        assert_eq!(edges[1], ("b".to_string(), "c".to_string(), "says: hi;".to_string()));
+        round_trip(&graph);
+    }
+
+    #[test]
+    fn repeated_labels() {
+        let mut graph = Graph::new();
+        let a = graph.add_vertex("new node".to_string(), [0.0, 0.0]);
+        let b = graph.add_vertex("new node".to_string(), [0.0, 0.0]);
+        let c = graph.add_vertex("new node".to_string(), [0.0, 0.0]);
+        graph.add_edge(a, b);
+        graph.add_edge(c, c);
+        let e = graph.add_edge(b, a).unwrap();
+        graph.edge_mut(e).unwrap().label = "back".to_string();
+
+        let text = graph_to_string(&graph);
+        assert_eq!(text, "new node #1;\nnew node #2;\nnew node #3;\n\n\
+                          new node #1 => new node #2;\nnew node #2 => new node #1 : back;\nnew node #3 => new node #3;");
+        let parsed = round_trip(&graph);
+        let ids = parsed.vertex_ids();
+        assert!(parsed.find_edge(ids[0], ids[1]).is_some());
+        assert_eq!(parsed.edge(parsed.find_edge(ids[1], ids[0]).unwrap()).unwrap().label, "back");
+        assert!(parsed.find_edge(ids[2], ids[2]).is_some());
+    }
+
+    #[test]
+    fn names_refer_to_vertices() {
+        let graph = parse_graph("x #a; x #b; x #a => x #b; y #only => z;").unwrap();
+        assert_eq!(shape(&graph).0, vec!["x", "x", "y", "z"]);
+        let ids = graph.vertex_ids();
+        assert!(graph.find_edge(ids[0], ids[1]).is_some());
+        assert!(graph.find_edge(ids[2], ids[3]).is_some());
+    }
+
+    #[test]
+    fn typed_targets() {
+        let graph = parse_graph("bob; bob => alice (Person) : knows;").unwrap();
+        assert_eq!(shape(&graph), (vec!["bob".to_string(), "alice (Person)".to_string()],
+                                   vec![("bob".to_string(), "alice (Person)".to_string(), "knows".to_string())]));
+    }
+
+    #[test]
+    fn self_loops() {
+        let graph = parse_graph("a; a => a;").unwrap();
+        let a = graph.vertex_ids()[0];
+        assert!(graph.find_edge(a, a).is_some());
+        round_trip(&graph);
+    }
+
+    #[test]
+    fn error_positions() {
+        let error = |text: &str| {
+            let e = parse_graph(text).unwrap_err();
+            (e.line, e.column, e.message)
+        };
+        assert_eq!(error("a;\nb => ;"), (2, 6, "expected a vertex label".to_string()));
+        assert_eq!(error("a;\n  b"), (2, 4, "expected `;` or `=>`".to_string()));
+        assert_eq!(error("a => b : x y z"), (1, 15, "expected `;`".to_string()));
+        assert_eq!(error("x;\n\"open"), (2, 1, "unterminated quoted label".to_string()));
+        assert_eq!(error("a => b;\na => b;"), (2, 1, "duplicate connection `a => b`".to_string()));
+        assert_eq!(error("a; a; a => b;"), (1, 7, "`a` is declared more than once, so it needs a `#` name".to_string()));
+        assert_eq!(error("a #x; a #x;"), (1, 7, "`a #x` is declared more than once".to_string()));
+        assert_eq!(error("a # => b;"), (1, 4, "expected a name after `#`".to_string()));
+    }
+
+    #[test]
+    fn cycles_in_acyclic_graphs() {
+        assert!(parse_acyclic_graph("a => b; b => c;").is_ok());
+        let e = parse_acyclic_graph("a => b;\nb => a;").unwrap_err();
+        assert_eq!((e.line, e.column, e.message), (2, 1, "`b => a` closes a cycle".to_string()));
+        assert!(parse_acyclic_graph("a => a;").is_err());
+    }
+}
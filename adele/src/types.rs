@@ -0,0 +1,241 @@
+//! The type language of the TYPE_TEXT pane, which declares the kinds of
+//! vertices a graph is made of:
+//!
+//! ```text
+//! # people and where they live
+//! type Person;
+//! type City, Country;
+//! ```
+//!
+//! Type names are letters, digits and `_`, not starting with a digit, and a
+//! `#` starts a comment that runs to the end of the line.
+//!
+//! A vertex label refers to a type by ending in the type's name in parentheses,
+//! as in `alice (Person)`, or by being nothing but the type's name. Parentheses
+//! mean nothing to the graph text format, so `bob => alice (Person);` connects
+//! two vertices there like any other labels would. Text without any declarations
+//! doesn't constrain the graph at all.
+
+use std::collections::HashMap;
+
+use graph::Graph;
+use lexer::{Cursor, Pos};
+use text::ParseError;
+
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Comma,
+    Semicolon,
+    Other(char)
+}
+
+struct Lexer {
+    cursor: Cursor
+}
+
+impl Lexer {
+    fn new(text: &str) -> Self {
+        Lexer { cursor: Cursor::new(text) }
+    }
+
+    /// Returns the next token and where it starts, or `None` at the end of the text.
+    fn next(&mut self) -> Option<(Token, Pos)> {
+        loop {
+            match self.cursor.peek(0) {
+                Some('#') => while self.cursor.peek(0).is_some_and(|c| c != '\n') {
+                    self.cursor.bump();
+                },
+                Some(c) if c.is_whitespace() => { self.cursor.bump(); },
+                _ => break
+            }
+        }
+
+        let start = self.cursor.pos;
+        let token = match self.cursor.bump()? {
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            c if is_name_start(c) => {
+                let mut name = c.to_string();
+                while let Some(c) = self.cursor.peek(0).filter(|&c| is_name_char(c)) {
+                    name.push(c);
+                    self.cursor.bump();
+                }
+                Token::Name(name)
+            },
+            c => Token::Other(c)
+        };
+        Some((token, start))
+    }
+
+    /// Skips past the next `;`, to carry on after an error.
+    fn recover(&mut self) {
+        while let Some((token, _)) = self.next() {
+            if token == Token::Semicolon {
+                break;
+            }
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The types declared in a type text, in the order they're declared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Types {
+    names: Vec<String>
+}
+
+impl Types {
+    /// Reads the declarations in `text`. Statements with an error in them are
+    /// skipped, so the result holds everything else along with every error found.
+    pub fn parse(text: &str) -> (Types, Vec<ParseError>) {
+        let mut lexer = Lexer::new(text);
+        let mut names: Vec<String> = vec![];
+        let mut declared_at: HashMap<String, Pos> = HashMap::new();
+        let mut errors = vec![];
+
+        'statements: while let Some((token, pos)) = lexer.next() {
+            match token {
+                Token::Name(ref keyword) if keyword == "type" => (),
+                Token::Semicolon => continue,
+                _ => {
+                    errors.push(pos.error("expected `type`"));
+                    lexer.recover();
+                    continue;
+                }
+            }
+
+            let mut statement = vec![];
+            loop {
+                let (name, pos) = match lexer.next() {
+                    Some((Token::Name(name), pos)) => (name, pos),
+                    Some((Token::Semicolon, pos)) => {
+                        errors.push(pos.error("expected a type name"));
+                        continue 'statements;
+                    },
+                    Some((_, pos)) => {
+                        errors.push(pos.error("expected a type name"));
+                        lexer.recover();
+                        continue 'statements;
+                    },
+                    None => {
+                        errors.push(lexer.cursor.pos.error("expected a type name"));
+                        break 'statements;
+                    }
+                };
+                statement.push((name, pos));
+
+                match lexer.next() {
+                    Some((Token::Comma, _)) => (),
+                    Some((Token::Semicolon, _)) => break,
+                    Some((_, pos)) => {
+                        errors.push(pos.error("expected `,` or `;`"));
+                        lexer.recover();
+                        continue 'statements;
+                    },
+                    None => {
+                        errors.push(lexer.cursor.pos.error("expected `,` or `;`"));
+                        break 'statements;
+                    }
+                }
+            }
+
+            for (name, pos) in statement {
+                if let Some(first) = declared_at.get(&name) {
+                    errors.push(pos.error(format!(
+                        "`{}` is already declared at {}:{}", name, first.line, first.column)));
+                    continue;
+                }
+                declared_at.insert(name.clone(), pos);
+                names.push(name);
+            }
+        }
+
+        (Types { names }, errors)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// Explains every vertex whose label doesn't refer to a declared type,
+    /// in creation order. Nothing is wrong when no types are declared.
+    pub fn check_graph(&self, graph: &Graph) -> Vec<String> {
+        if self.names.is_empty() {
+            return vec![];
+        }
+        graph.vertices().filter_map(|(_, v)| {
+            let name = type_of(&v.label);
+            if self.is_declared(name) {
+                None
+            } else if name.is_empty() {
+                Some(format!("`{}` doesn't name a type", v.label))
+            } else {
+                Some(format!("`{}` refers to `{}`, which isn't a declared type", v.label, name))
+            }
+        }).collect()
+    }
+}
+
+/// The name of the type a vertex label refers to: what's in the parentheses it
+/// ends with, or the whole label if it doesn't end with any.
+pub fn type_of(label: &str) -> &str {
+    let label = label.trim();
+    match (label.strip_suffix(')'), label.rfind('(')) {
+        (Some(rest), Some(open)) => rest[open + 1..].trim(),
+        _ => label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn types_of_labels() {
+        assert_eq!(type_of("alice (Person)"), "Person");
+        assert_eq!(type_of("alice ( Person )"), "Person");
+        assert_eq!(type_of("f(x) (City)"), "City");
+        assert_eq!(type_of(" Person "), "Person");
+        assert_eq!(type_of("alice : Person"), "alice : Person");
+        assert_eq!(type_of("alice ()"), "");
+    }
+
+    #[test]
+    fn declarations_and_errors() {
+        let (types, errors) = Types::parse("# people\ntype Person;\ntype City, Person;\nkind X;\ntype 1;");
+        assert_eq!(types.names(), &["Person".to_string(), "City".to_string()]);
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["3:12: `Person` is already declared at 2:6",
+                                "4:1: expected `type`",
+                                "5:6: expected a type name"]);
+    }
+
+    #[test]
+    fn checking_a_graph() {
+        let mut graph = Graph::new();
+        graph.add_vertex("alice (Person)".to_string(), [0.0, 0.0]);
+        graph.add_vertex("Person".to_string(), [0.0, 0.0]);
+        graph.add_vertex("paris (Town)".to_string(), [0.0, 0.0]);
+        graph.add_vertex("()".to_string(), [0.0, 0.0]);
+
+        let (types, _) = Types::parse("type Person, City;");
+        assert_eq!(types.check_graph(&graph), vec![
+            "`paris (Town)` refers to `Town`, which isn't a declared type".to_string(),
+            "`()` doesn't name a type".to_string()
+        ]);
+        assert!(Types::default().check_graph(&graph).is_empty());
+    }
+}
@@ -10,41 +10,428 @@ use conrod::widget::IndexSlot;
 
 use conrod;
 use std;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
+use adele::analysis::{creates_cycle, first_cycle_edge, Analysis};
+use adele::geometry::{self, Arrow, View};
+use adele::graph::{EdgeId, Graph, VertexId};
+use adele::history::{Change, History};
+use adele::layout::Bounds;
+use adele::layout::force::ForceLayout;
+use adele::layout::layered::{Direction, LayeredLayout};
+use adele::spatial::SpatialIndex;
+use adele::svg::SvgStyle;
+use adele::text::graph_to_string;
 
-struct Graph {
-    vertices: Vec<Box<Vertex>>
+
+#[derive(Clone)]
+struct VertexSlots {
+    fill: IndexSlot,
+    outline: IndexSlot,
+    text: IndexSlot
+}
+
+#[derive(Clone)]
+struct EdgeSlots {
+    line: IndexSlot,
+    arrow: IndexSlot,
+    label: IndexSlot
+}
+
+impl VertexSlots {
+    fn new() -> Self {
+        VertexSlots { fill: IndexSlot::new(), outline: IndexSlot::new(), text: IndexSlot::new() }
+    }
+}
+
+impl EdgeSlots {
+    fn new() -> Self {
+        EdgeSlots { line: IndexSlot::new(), arrow: IndexSlot::new(), label: IndexSlot::new() }
+    }
 }
 
-struct Vertex {
-    outs: Vec<(*mut Vertex, IndexSlot, IndexSlot)>,
-    ins: Vec<*mut Vertex>,
 
-    label: String,
-    position: [f64; 2],
-    fill_idx: IndexSlot,
-    outline_idx: IndexSlot,
-    text_idx: IndexSlot
+/// The vertices and edges that moving and deleting apply to.
+#[derive(Clone, Default, PartialEq)]
+struct Selection {
+    vertices: BTreeSet<VertexId>,
+    edges: BTreeSet<EdgeId>
 }
 
+impl Selection {
+    fn clear(&mut self) {
+        self.vertices.clear();
+        self.edges.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.edges.is_empty()
+    }
+}
+
+/// Adds `item` to `set` if it isn't there yet, otherwise takes it out.
+fn toggle<T: Ord>(set: &mut BTreeSet<T>, item: T) {
+    if !set.remove(&item) {
+        set.insert(item);
+    }
+}
 
 #[derive(Clone)]
 enum Mode {
-    // Moving a vertex from an initial location.
-    MovingVertex(usize, Point),
+    // Moving the selected vertices from their initial locations.
+    Moving(Vec<(VertexId, Point)>),
 
     // Creating an edge starting at the given vertex.
-    // The IndexSlots are used for the visual line and arrow.
-    CreatingEdge(usize, IndexSlot, IndexSlot, Point),
+    // The slots are used for the visual line and arrow.
+    CreatingEdge(VertexId, EdgeSlots, Point),
+
+    // Dragging the view around, starting from the given view.
+    Panning(View),
+
+    // Dragging a selection box from the first corner to the second.
+    // The selection is what was selected before, which the box adds to.
+    Selecting(Point, Point, Selection),
 
     Idle
 }
 
-pub struct State {
+/// A graph and the history of its edits, owned by the application and shown
+/// and edited by any number of `GraphWidget`s.
+///
+/// The application can change the graph too, through `graph_mut`, and make
+/// its changes undoable with `record`. Every change is counted, so widgets
+/// notice and report it however it was made.
+pub struct Model {
     graph: Graph,
+    history: History,
+    // Counts the changes to the graph, recorded or not.
+    revision: u64,
+    // Counts the calls to `replace`, so that widgets can tell when to start over.
+    generation: u64
+}
+
+impl Model {
+    pub fn new(graph: Graph) -> Self {
+        Model { graph, history: History::new(), revision: 0, generation: 0 }
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    /// The graph, for changing it. This counts as a change whether or not anything
+    /// is changed, so it's best not called on every update.
+    pub fn graph_mut(&mut self) -> &mut Graph {
+        self.revision += 1;
+        &mut self.graph
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Adds a change that was just made to the graph to the history, so it can be undone.
+    pub fn record(&mut self, change: Change) {
+        self.revision += 1;
+        self.history.record(change);
+    }
+
+    /// Like `record`, for a keystroke's worth of typing, which the history merges
+    /// with the keystrokes before it until `finish_typing`.
+    pub fn record_typing(&mut self, change: Change) {
+        self.revision += 1;
+        self.history.record_typing(change);
+    }
+
+    /// Makes the next typing a change of its own. The graph stays as it is, so
+    /// this isn't counted as a change.
+    pub fn finish_typing(&mut self) {
+        self.history.finish_typing();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let changed = self.history.undo(&mut self.graph);
+        if changed {
+            self.revision += 1;
+        }
+        changed
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let changed = self.history.redo(&mut self.graph);
+        if changed {
+            self.revision += 1;
+        }
+        changed
+    }
+
+    /// Goes up with every change to the graph, whether it went through the history
+    /// or `graph_mut`. While it stays the same, the graph does too.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Swap in a whole other graph, e.g. after opening a document. The history
+    /// is cleared, and widgets forget their view and selection.
+    pub fn replace(&mut self, graph: Graph) {
+        self.graph = graph;
+        self.history.clear();
+        self.revision += 1;
+        self.generation += 1;
+    }
+}
+
+pub struct State {
+    vertex_slots: HashMap<VertexId, VertexSlots>,
+    edge_slots: HashMap<EdgeId, EdgeSlots>,
     mode: Mode,
+    selection: Selection,
+    selection_box: IndexSlot,
+    // The subgraph that was copied last, for pasting.
+    copied: Option<Graph>,
+    view: View,
+    // Space turns left-dragging into panning while it's held.
+    space_held: bool,
+    // Where the vertices and edges are, for hit testing and for leaving out what's off screen.
+    index: SpatialIndex,
+    // The model revision that the index, slots, selection and mode were last checked against.
+    caught_up: Option<u64>,
+    // The label box typed into last, while more typing into it merges in the history.
+    typing: Option<widget::Index>,
+    // The fills of the vertices in cyclic components, and the model revision they're for.
+    analyzed: Option<(u64, HashMap<VertexId, color::Color>)>,
+    // The model generation this state belongs to.
+    generation: u64,
+    // The model revision and graph that events were last reported for,
+    // and the selection as it was then.
+    reported: Option<u64>,
+    reported_graph: Graph,
+    reported_selection: Selection
+}
+
+/// Something that happened to the graph or the selection during an update.
+///
+/// Edits are found by comparing the graph with how it was at the last update
+/// that changed it, so they come in this order: removed edges, removed vertices,
+/// added vertices, added edges, then moves and label changes. A vertex that's
+/// being dragged, or laid out live until the layout settles, is reported as
+/// moving on every update.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    VertexAdded(VertexId),
+    VertexRemoved(VertexId),
+    VertexMoved(VertexId),
+    LabelChanged(VertexId),
+    EdgeAdded(EdgeId),
+    EdgeRemoved(EdgeId),
+    EdgeLabelChanged(EdgeId),
+    SelectionChanged { vertices: Vec<VertexId>, edges: Vec<EdgeId> },
+    // The model's graph was replaced. Nothing is reported about what it replaced.
+    GraphReplaced
+}
+
+/// The edits that turn `before` into `after`.
+fn changes(before: &Graph, after: &Graph) -> Vec<Event> {
+    let mut events = vec![];
+    for (id, _) in before.edges() {
+        if after.edge(id).is_none() {
+            events.push(Event::EdgeRemoved(id));
+        }
+    }
+    for (id, _) in before.vertices() {
+        if after.vertex(id).is_none() {
+            events.push(Event::VertexRemoved(id));
+        }
+    }
+    for (id, _) in after.vertices() {
+        if before.vertex(id).is_none() {
+            events.push(Event::VertexAdded(id));
+        }
+    }
+    for (id, e) in after.edges() {
+        match before.edge(id) {
+            // an ID that's back between other vertices is a different edge
+            Some(old) if (old.source(), old.target()) != (e.source(), e.target()) => {
+                events.push(Event::EdgeRemoved(id));
+                events.push(Event::EdgeAdded(id));
+            },
+            Some(_) => (),
+            None => events.push(Event::EdgeAdded(id))
+        }
+    }
+    for (id, v) in after.vertices() {
+        if let Some(old) = before.vertex(id) {
+            if old.position != v.position {
+                events.push(Event::VertexMoved(id));
+            }
+            if old.label != v.label {
+                events.push(Event::LabelChanged(id));
+            }
+        }
+    }
+    for (id, e) in after.edges() {
+        if before.edge(id).map_or(false, |old| old.label != e.label) {
+            events.push(Event::EdgeLabelChanged(id));
+        }
+    }
+    events
+}
+
+/// Drop what the state holds for vertices and edges that are no longer in the graph,
+/// after a change that could have removed any of them.
+fn forget_stale(state: &mut State, graph: &Graph) {
+    let State { ref mut vertex_slots, ref mut edge_slots, ref mut mode, ref mut selection, .. } = *state;
+    vertex_slots.retain(|&id, _| graph.vertex(id).is_some());
+    edge_slots.retain(|&id, _| graph.edge(id).is_some());
+    selection.vertices.retain(|&id| graph.vertex(id).is_some());
+    selection.edges.retain(|&id| graph.edge(id).is_some());
+    let stale = match *mode {
+        Mode::Moving(ref starts) => starts.iter().any(|&(id, _)| graph.vertex(id).is_none()),
+        Mode::CreatingEdge(id, _, _) => graph.vertex(id).is_none(),
+        Mode::Panning(_) | Mode::Selecting(_, _, _) | Mode::Idle => false
+    };
+    if stale {
+        *mode = Mode::Idle;
+    }
 }
 
+// Fills for the strongly connected components that have cycles, taken in turn.
+const COMPONENT_COLORS: [color::Color; 6] = [
+    color::Color::Rgba(1.0, 0.85, 0.85, 1.0),
+    color::Color::Rgba(0.85, 0.93, 1.0, 1.0),
+    color::Color::Rgba(0.87, 1.0, 0.85, 1.0),
+    color::Color::Rgba(1.0, 0.95, 0.8, 1.0),
+    color::Color::Rgba(0.93, 0.87, 1.0, 1.0),
+    color::Color::Rgba(0.85, 1.0, 0.97, 1.0)
+];
+
+// Side of the squares the spatial index divides the graph into.
+const INDEX_CELL_SIZE: Scalar = 200.0;
+
+// A live layout step that moves no vertex further than this is taken back,
+// so that a settled layout leaves the graph, and everything drawn from it, alone.
+const LIVE_LAYOUT_SETTLED: Scalar = 0.1;
+
+/// Bring the spatial index up to date with whatever happened to the graph, and
+/// forget what's no longer in it. Nothing needs doing while the model hasn't changed.
+fn catch_up(state: &mut State, model: &Model, radius: Scalar) {
+    if state.caught_up == Some(model.revision()) && state.index.radius() == radius {
+        return;
+    }
+    forget_stale(state, &model.graph);
+    state.index.set_radius(radius);
+    state.index.update(&model.graph);
+    state.caught_up = Some(model.revision());
+}
+
+const ZOOM_STEP: Scalar = 1.1;
+const MIN_ZOOM: Scalar = 0.1;
+const MAX_ZOOM: Scalar = 10.0;
+
+/// Removes the selected vertices and edges as a single step in the history.
+fn delete_selection(state: &mut State, model: &mut Model) {
+    let selection = std::mem::replace(&mut state.selection, Selection::default());
+    let mut changes = vec![];
+    for &edge in selection.edges.iter() {
+        if let Some(removed) = model.graph.remove_edge(edge) {
+            changes.push(Change::RemoveEdge {
+                id: edge,
+                source: removed.source(),
+                target: removed.target(),
+                label: removed.label
+            });
+        }
+    }
+    for &id in selection.vertices.iter() {
+        if let Some((vertex, edges)) = model.graph.remove_vertex(id) {
+            changes.push(Change::RemoveVertex { id: id, label: vertex.label, position: vertex.position, edges: edges });
+        }
+    }
+    if !changes.is_empty() {
+        model.record(Change::Batch(changes));
+    }
+    forget_stale(state, &model.graph);
+}
+
+/// Copies the selected vertices and the edges among them for pasting later,
+/// and puts them on the system clipboard in the text format if that's enabled.
+fn copy_selection(state: &mut State, graph: &Graph) {
+    if state.selection.vertices.is_empty() {
+        return;
+    }
+    let ids: Vec<VertexId> = state.selection.vertices.iter().cloned().collect();
+    let copied = graph.subgraph(&ids);
+    to_system_clipboard(graph_to_string(&copied));
+    state.copied = Some(copied);
+}
+
+/// Adds a fresh copy of what was copied last, centered on `at`, and selects it.
+/// In DAG mode, a copy with a cycle isn't pasted, and the message says why.
+fn paste(state: &mut State, model: &mut Model, at: Point, dag: bool) -> Option<String> {
+    let copied = match state.copied {
+        Some(ref copied) if copied.vertex_count() > 0 => copied.clone(),
+        _ => return None
+    };
+    // the copy is only connected to itself, so it's the only place a cycle could come from
+    if let Some((source, target)) = if dag { cycle_edge(&copied) } else { None } {
+        return Some(format!("The copied edge from {} to {} closes a cycle", source, target));
+    }
+
+    let mut low = [std::f64::INFINITY; 2];
+    let mut high = [std::f64::NEG_INFINITY; 2];
+    for (_, v) in copied.vertices() {
+        for k in 0..2 {
+            low[k] = low[k].min(v.position[k]);
+            high[k] = high[k].max(v.position[k]);
+        }
+    }
+    let middle = [(low[0] + high[0]) / 2.0, (low[1] + high[1]) / 2.0];
+
+    let added = model.graph.add_graph(&copied, [at[0] - middle[0], at[1] - middle[1]]);
+
+    // the vertices come first, since their edges can't be put back without them
+    let mut changes = vec![];
+    for &id in added.iter() {
+        let v = model.graph.vertex(id).unwrap();
+        changes.push(Change::AddVertex { id: id, label: v.label.clone(), position: v.position });
+    }
+    for &id in added.iter() {
+        for &e in model.graph.vertex(id).unwrap().outs().iter() {
+            let edge = model.graph.edge(e).unwrap();
+            changes.push(Change::AddEdge { id: e, source: id, target: edge.target() });
+            if !edge.label.is_empty() {
+                changes.push(Change::SetEdgeLabel { id: e, from: String::new(), to: edge.label.clone() });
+            }
+        }
+    }
+    model.record(Change::Batch(changes));
+
+    state.selection.clear();
+    state.selection.vertices.extend(added);
+    None
+}
+
+/// The labels at either end of the first edge that closes a cycle, if there is one.
+fn cycle_edge(graph: &Graph) -> Option<(String, String)> {
+    first_cycle_edge(graph).map(|edge| {
+        let e = graph.edge(edge).unwrap();
+        let label = |id| graph.vertex(id).unwrap().label.clone();
+        (label(e.source()), label(e.target()))
+    })
+}
+
+#[cfg(feature = "clipboard")]
+fn to_system_clipboard(text: String) {
+    use clipboard::{ClipboardContext, ClipboardProvider};
+    // not having a clipboard shouldn't get in the way of copying within the widget
+    if let Ok(mut context) = ClipboardContext::new() {
+        let _ = context.set_contents(text);
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn to_system_clipboard(_text: String) {}
+
 widget_style!{
     style Style {
         - vertex_radius: Scalar { 35.0 }
@@ -54,21 +441,106 @@ widget_style!{
         - edge_color: color::Color { color::rgb(0.2, 0.2, 0.2) }
         - arrow_base: Scalar { 15.0 }
         - arrow_height: Scalar { 10.0 }
+
+        - selection_color: color::Color { color::rgb(0.2, 0.5, 1.0) }
+    }
+}
+
+impl Style {
+    /// The sizes and colors the widget draws with, for drawing the graph as an SVG picture.
+    pub fn svg_style(&self, theme: &conrod::Theme) -> SvgStyle {
+        SvgStyle {
+            vertex_radius: self.vertex_radius(theme),
+            vertex_outline_color: self.vertex_outline_color(theme).to_fsa(),
+            vertex_fill_color: self.vertex_fill_color(theme).to_fsa(),
+            edge_color: self.edge_color(theme).to_fsa(),
+            arrow_base: self.arrow_base(theme),
+            arrow_height: self.arrow_height(theme),
+            label_color: theme.label_color.to_fsa(),
+            ..SvgStyle::default()
+        }
     }
 }
 
-pub struct GraphWidget {
+pub struct GraphWidget<'a> {
     common: widget::CommonBuilder,
-    style: Style
+    style: Style,
+    model: &'a mut Model,
+    sync: Option<&'a Graph>,
+    analysis: Option<&'a mut String>,
+    message: Option<&'a mut String>,
+    dag: bool,
+    layout: Option<Layout>,
+    live_layout: bool
 }
 
-impl GraphWidget {
-    pub fn new() -> Self {
+/// The automatic layouts the widget can apply to its graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    Force,
+    Layered(Direction)
+}
+
+impl<'a> GraphWidget<'a> {
+    /// A view of `model`, which edits go straight into.
+    pub fn new(model: &'a mut Model) -> Self {
         GraphWidget {
             common: widget::CommonBuilder::new(),
-            style: Style::new()
+            style: Style::new(),
+            model: model,
+            sync: None,
+            analysis: None,
+            message: None,
+            dag: false,
+            layout: None,
+            live_layout: false
         }
     }
+
+    /// Make the graph match the labels and edges of `graph` before drawing.
+    /// Vertices that survive keep their position, new ones are placed near the middle.
+    /// The change is recorded as typing, so a run of syncs from text being typed is
+    /// undone in one step until the application calls `Model::finish_typing`.
+    pub fn sync_with(mut self, graph: Option<&'a Graph>) -> Self {
+        self.sync = graph;
+        self
+    }
+
+    /// Color each strongly connected component that has a cycle, and write a report
+    /// on the cycles, topological order, sources, sinks and unreachable vertices
+    /// into `out` once this update is done. The graph is only analyzed again after it
+    /// changed, and `out` is left alone until then.
+    pub fn analysis(mut self, out: Option<&'a mut String>) -> Self {
+        self.analysis = out;
+        self
+    }
+
+    /// Write a message for the user into `out` when an edit is refused during this update.
+    /// `out` is left alone otherwise.
+    pub fn message(mut self, out: Option<&'a mut String>) -> Self {
+        self.message = out;
+        self
+    }
+
+    /// DAG mode: refuse new edges, pastes, undos and redos that would close a
+    /// cycle. The graph should have no cycles when this is turned on.
+    pub fn dag(mut self, dag: bool) -> Self {
+        self.dag = dag;
+        self
+    }
+
+    /// Rearrange the whole graph with the given layout during this update.
+    pub fn layout(mut self, layout: Option<Layout>) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Advance a force-directed layout by one step on every update, so the graph
+    /// keeps settling while it's being edited. These steps aren't undoable.
+    pub fn live_layout(mut self, live: bool) -> Self {
+        self.live_layout = live;
+        self
+    }
 }
 
 fn dist(a: Point, b: Point) -> Scalar {
@@ -78,93 +550,59 @@ fn dist(a: Point, b: Point) -> Scalar {
     (dx*dx + dy*dy).sqrt()
 }
 
-// Note: The vertices are listed in the order that they are stored in the graph.
-fn graph_to_string(g: &Graph) -> String {
-    fn join<'a, I>(mut input: I, separator: &str) -> String
-    where I: Iterator<Item=&'a str> {
-        let first = input.next().unwrap_or(&"").to_string();
-        input.fold(first, |acc, s| acc + separator + s)
-    }
-
-    let dec_lines: Vec<_> = g.vertices.iter().map(|v| v.label.clone() + ";").collect();
-    let declarations: String = join(dec_lines.iter().map(|d| d.as_str()), "\n");
-    let con_lines: Vec<_> = g.vertices.iter()
-        .flat_map(|source| {
-            source.outs.iter().map(move |target| {
-                let left = (**source).label.clone();
-                let right = unsafe { (*target.0).label.as_str() };
-                left + " => " + right + ";"
-            })
-        }).collect();
-    let connections = join(con_lines.iter().map(|c| c.as_str()), "\n");
-    
-    declarations + "\n\n" + &connections
-}
-
+/// Draws an arrow that's already on the screen, with its line thickness scaled
+/// by `zoom`. A `label` is shown in a text box beside the arrow, and if it was
+/// edited the new text is returned.
+fn draw_arrow(arrow: &Arrow, ui: &mut conrod::UiCell, edge_color: color::Color,
+              parent_idx: widget::Index, slots: &EdgeSlots, zoom: Scalar,
+              label: Option<&str>) -> Option<String> {
+    if !arrow.is_finite() {
+        return None;
+    }
 
-fn draw_arrow(start: Point, end: Point, ui: &mut conrod::UiCell, style: &Style,
-              parent_idx: widget::Index, line_idx: &IndexSlot, tip_idx: &IndexSlot,
-              subtract: Scalar) {
-    let arrow_height = style.arrow_height(&ui.theme);
-
-    let dx = end[0] - start[0];
-    let dy = end[1] - start[1];
-    let dist = (dx*dx + dy*dy).sqrt();
-    let norm_dx = dx / dist;
-    let norm_dy = dy / dist;
-    let new_dist = dist - subtract - arrow_height;
-    let new_dx = norm_dx * new_dist;
-    let new_dy = norm_dy * new_dist;
-
-    let new_to = [start[0] + new_dx, start[1] + new_dy];
-    
-    // arrow at the end of the line.
-    //         _      _
-    //        /0\     |
-    //       /   \    | h
-    //      /1   2\   |
-    //      -------   -
-    //         b
-    //
-    // We can use the normalized vector from before to build up
-    // the triangle. Keep in mind how 90 degree rotations work
-    // in 2D space:
-    //
-    // rotate left: (x, y) => (-y, x)
-    // rotate right: (x, y) => (y, -x)
-    // rotate 180: (x, y) => (-y, -x)
-
-    let b = style.arrow_base(&ui.theme);
-    let h = style.arrow_height(&ui.theme);
-
-    let h_vector = [norm_dx * h, norm_dy * h];
-    let b_vector = [norm_dx * b / 2.0, norm_dy * b / 2.0];
-
-    let triangle = vec![
-        [new_to[0] + h_vector[0], new_to[1] + h_vector[1]],
-        [new_to[0] - b_vector[1], new_to[1] + b_vector[0]],
-        [new_to[0] + b_vector[1], new_to[1] - b_vector[0]]
-    ];
-    
-    let edge_color = style.edge_color(&ui.theme);
-    primitive::line::Line::abs(start, new_to)
+    primitive::point_path::PointPath::abs(arrow.path.clone())
         .color(edge_color)
-        .thickness(2.0)
+        .thickness(2.0 * zoom)
         .graphics_for(parent_idx)
         .parent(parent_idx)
-        .set(line_idx.get(ui), ui);
+        .set(slots.line.get(ui), ui);
 
-    primitive::shape::polygon::Polygon::fill(triangle)
+    primitive::shape::polygon::Polygon::fill(arrow.head.to_vec())
         .color(edge_color)
         .graphics_for(parent_idx)
         .parent(parent_idx)
-        .set(tip_idx.get(ui), ui);
+        .set(slots.arrow.get(ui), ui);
+
+    let label = match label {
+        Some(label) => label,
+        None => return None
+    };
+
+    let font_size = ((12.0 * zoom).round() as u32).max(1);
+    let char_width = (font_size as f64) * 0.692;
+    let box_width = 30.0 * zoom + char_width * (label.len() as f64);
+    let mut text = label.to_string();
+    let mut edited = None;
+    for event in widget::text_box::TextBox::new(&mut text)
+        .xy(arrow.label_position(1.25 * 12.0 * zoom))
+        .wh([box_width, 25.0 * zoom])
+        .font_size(font_size)
+        .align_text_middle()
+        .parent(parent_idx)
+        .set(slots.label.get(ui), ui)
+    {
+        match event {
+            widget::text_box::Event::Update(string) => edited = Some(string),
+            _ => ()
+        }
+    }
+    edited
 }
 
-impl Widget for GraphWidget {
+impl<'a> Widget for GraphWidget<'a> {
     type State = State;
     type Style = Style;
-    type Event = String;
+    type Event = Vec<Event>;
 
     fn common(&self) -> &widget::CommonBuilder {
         &self.common
@@ -175,30 +613,23 @@ impl Widget for GraphWidget {
     }
 
     fn init_state(&self) -> Self::State {
-        let mut v0 = Box::new(Vertex {
-            outs: vec![],
-            ins: vec![],
-            label: "Hello world!".to_string(),
-            position: [-200.0, -100.0],
-            fill_idx: IndexSlot::new(),
-            outline_idx: IndexSlot::new(),
-            text_idx: IndexSlot::new()
-        });
-
-        let mut v1 = Box::new(Vertex {
-            outs: vec![(&mut *v0, IndexSlot::new(), IndexSlot::new())],
-            ins: vec![],
-            label: "Holy smokes!".to_string(),
-            position: [-200.0, 100.0],
-            fill_idx: IndexSlot::new(),
-            outline_idx: IndexSlot::new(),
-            text_idx: IndexSlot::new()
-        });
-        v0.ins.push(&mut *v1);
-
         State {
-            graph: Graph { vertices: vec![v0, v1] },
+            vertex_slots: HashMap::new(),
+            edge_slots: HashMap::new(),
             mode: Mode::Idle,
+            selection: Selection::default(),
+            selection_box: IndexSlot::new(),
+            copied: None,
+            view: View::new(),
+            space_held: false,
+            index: SpatialIndex::new(INDEX_CELL_SIZE, 0.0),
+            caught_up: None,
+            typing: None,
+            analyzed: None,
+            generation: self.model.generation,
+            reported: None,
+            reported_graph: self.model.graph.clone(),
+            reported_selection: Selection::default()
         }
     }
 
@@ -208,74 +639,83 @@ impl Widget for GraphWidget {
 
     fn update(self, args: widget::UpdateArgs<Self>) -> Self::Event {
         let widget::UpdateArgs { idx, state, style, rect, mut ui, ..} = args;
+        let model = self.model;
 
         let radius = style.vertex_radius(&ui.theme);
+        let dag = self.dag;
+        let mut message = None;
 
         let in_widget_space = |xy: Point| {
             [rect.x() + xy[0], rect.y() + xy[1]]
         }; 
 
-        let vertex_at_point = |state: &State, xy: Point| {
-            state.graph.vertices.iter().position(|v| dist(v.position, xy) < radius)
+        // Where a point relative to the widget is in the graph.
+        let in_graph_space = |state: &State, xy: Point| {
+            state.view.to_graph(in_widget_space(xy))
         };
 
-        // Clamp a point within this widget's rectangle.
-        // The coordinates are in widget space.
-        let clamp = |xy: Point, padding: Scalar| {
-            let x = rect.x.pad(padding).clamp_value(xy[0]);
-            let y = rect.y.pad(padding).clamp_value(xy[1]);
-            [x, y]
+        let vertex_at_point = |state: &State, graph: &Graph, xy: Point| {
+            state.index.vertices_at(xy).into_iter().find(|&id| {
+                graph.vertex(id).map_or(false, |v| dist(v.position, xy) < radius)
+            })
         };
 
-        /// If there is an edge at the given point, this returns its source vertex's
-        /// index, along with the destination vertex's index in the source's `outs` vec.
-        fn edge_at_point(state: &State, p: Point) -> Option<(usize, usize)> {
-            let width = 6.0; // make the clickable width of the edge bigger than the draw width
-            let halfwidth = width/2.0;
-
-            fn dot(r: Point, s: Point) -> Scalar {
-                r[0]*s[0] + r[1]*s[1]
-            }
-
-            for (vindex, source) in state.graph.vertices.iter().enumerate() {
-                let pos = source.outs.iter().position(|&(ref targetptr, _, _)| {
-                    let u = source.position;
-                    let v = unsafe { (**targetptr).position };
+        let arrow_base = style.arrow_base(&ui.theme);
+        let arrow_height = style.arrow_height(&ui.theme);
 
-                    let uv = [v[0]-u[0], v[1]-u[1]];
-                    let mag_uv = (uv[0]*uv[0] + uv[1]*uv[1]).sqrt();
-                    let norm_uv = [uv[0]/mag_uv, uv[1]/mag_uv];
-
-                    // Let `a` be a corner of the rectangle with neighbors
-                    // `b` and `c`. Let `p` be the point we're testing.
-
-                    // Remember (x, y) => (-y, x) is a 90 degree counterclockwise rotation.
-                    let a = [u[0] - halfwidth * norm_uv[1], u[1] + halfwidth * norm_uv[0]];
-                    let ab = uv;
-                    // Remember (x, y) => (y, -x) is a 90 degree clockwise rotation.
-                    let ac = [width * norm_uv[1], - width * norm_uv[0]];
-                    let ap = [p[0]-a[0], p[1]-a[1]];
-
-                    // Notice that ab and ac are perpendicular sides of the rectangle.
+        // If there is an edge at the given point, this returns its ID.
+        // `width` is how wide the clickable part of an edge is, in graph coordinates.
+        let edge_at_point = |state: &State, graph: &Graph, p: Point, width: Scalar| {
+            let near = Bounds::new(p[0] - width / 2.0, p[0] + width / 2.0, p[1] - width / 2.0, p[1] + width / 2.0);
+            state.index.edges_in(&near).into_iter().find(|&id| {
+                geometry::edge_arrow(graph, id, radius, arrow_base, arrow_height)
+                    .map_or(false, |arrow| arrow.distance_to(p) <= width / 2.0)
+            })
+        };
 
-                    // To be inside the rectangle we need the scalar projection of
-                    // ap onto both ab and ac to be positive and not too large.
-                    // So that's what's checked below, it's just written slightly differently
-                    // to turn divisions into multiplications.
+        let mut events = vec![];
+
+        if state.generation != model.generation {
+            events.push(Event::GraphReplaced);
+            state.update(|state| {
+                state.generation = model.generation;
+                state.reported = Some(model.revision());
+                state.reported_graph = model.graph.clone();
+                state.vertex_slots.clear();
+                state.edge_slots.clear();
+                state.mode = Mode::Idle;
+                state.selection.clear();
+                state.view = View::new();
+            });
+        }
 
-                    let tmp1 = dot(ap, ab);
-                    let tmp2 = dot(ap, ac);
-                    return (0_f64 <= tmp1 && tmp1 <= dot(ab, ab)) &&
-                           (0_f64 <= tmp2 && tmp2 <= dot(ac, ac));
-                });
+        if let Some(other) = self.sync {
+            state.update(|state| {
+                let before = model.graph.clone();
+                let added = model.graph.sync_from(other);
+
+                // lay new vertices out in a row across the middle of the view
+                let middle = state.view.to_graph(rect.xy());
+                let spacing = 2.5 * radius;
+                let row_width = spacing * (added.len() as Scalar - 1.0);
+                for (i, &id) in added.iter().enumerate() {
+                    let x = middle[0] - row_width / 2.0 + spacing * (i as Scalar);
+                    model.graph.vertex_mut(id).unwrap().position = [x, middle[1]];
+                }
 
-                if let Some(eindex) = pos {
-                    return Some((vindex, eindex));
+                if model.graph != before {
+                    let after = Box::new(model.graph.clone());
+                    model.record_typing(Change::Replace { before: Box::new(before), after: after });
                 }
-            }
-            return None;
+                forget_stale(state, &model.graph);
+            });
         }
 
+        // the application or another view may have changed anything since the last update
+        state.update(|state| catch_up(state, model, radius));
+
+        let mouse_xy = ui.widget_input(idx).mouse().map(|mouse| mouse.rel_xy());
+
         for widget_event in ui.widget_input(idx).events() {
             use conrod::input::state::mouse;
             use conrod::input::keyboard;
@@ -286,55 +726,161 @@ impl Widget for GraphWidget {
                     button: event::Button::Mouse(mouse::Button::Left, xy),
                     modifiers
                 }) => {
-                    let clicked_vertex = vertex_at_point(&state, in_widget_space(xy));
+                    let clicked_vertex = vertex_at_point(&state, &model.graph, in_graph_space(&state, xy));
 
                     match (&state.mode, modifiers, clicked_vertex) {
+                        // start panning
+                        (&Mode::Idle, _, _) if state.space_held =>
+                            state.update(|state| state.mode = Mode::Panning(state.view)),
+
                         // start creating edge
-                        (&Mode::Idle, keyboard::SHIFT, Some(index)) =>
-                            state.update(|state|
-                                state.mode = Mode::CreatingEdge(index, IndexSlot::new(),
-                                                                IndexSlot::new(), in_widget_space(xy))),
+                        (&Mode::Idle, keyboard::SHIFT, Some(id)) =>
+                            state.update(|state| {
+                                let position = in_graph_space(state, xy);
+                                state.mode = Mode::CreatingEdge(id, EdgeSlots::new(), position);
+                            }),
 
                         // create node
                         (&Mode::Idle, keyboard::SHIFT, None) =>
-                            state.update(|state|
-                                state.graph.vertices.push(Box::new(Vertex {
-                                    outs: vec![],
-                                    ins: vec![],
-                                    label: "new node".to_string(),
-                                    position: clamp(in_widget_space(xy), radius),
-                                    fill_idx: IndexSlot::new(),
-                                    outline_idx: IndexSlot::new(),
-                                    text_idx: IndexSlot::new()}))),
+                            state.update(|state| {
+                                let label = "new node".to_string();
+                                let position = in_graph_space(state, xy);
+                                let id = model.graph.add_vertex(label.clone(), position);
+                                model.record(Change::AddVertex { id: id, label: label, position: position });
+                            }),
                             
-                        // start moving vertex
-                        (&Mode::Idle, _, Some(index)) |
-                        (&Mode::MovingVertex(_,_), _, Some(index)) =>
-                            state.update(|state|
-                                state.mode = Mode::MovingVertex(index, state.graph.vertices[index].position)),
+                        // add the vertex to the selection, or take it out
+                        (&Mode::Idle, keyboard::CTRL, Some(id)) =>
+                            state.update(|state| toggle(&mut state.selection.vertices, id)),
+
+                        // start moving the selection, which becomes just this vertex
+                        // if it wasn't selected yet
+                        (&Mode::Idle, _, Some(id)) |
+                        (&Mode::Moving(_), _, Some(id)) =>
+                            state.update(|state| {
+                                if !state.selection.vertices.contains(&id) {
+                                    state.selection.clear();
+                                    state.selection.vertices.insert(id);
+                                }
+                                let graph = &model.graph;
+                                let starts = state.selection.vertices.iter()
+                                    .map(|&id| (id, graph.vertex(id).unwrap().position))
+                                    .collect();
+                                state.mode = Mode::Moving(starts);
+                            }),
+
+                        // select an edge, or start a selection box on empty canvas
+                        (&Mode::Idle, _, None) => {
+                            let p = in_graph_space(&state, xy);
+                            let add = modifiers == keyboard::CTRL;
+                            let clicked_edge = edge_at_point(&state, &model.graph, p, 6.0 / state.view.zoom);
+                            state.update(|state| {
+                                if !add {
+                                    state.selection.clear();
+                                }
+                                match clicked_edge {
+                                    Some(edge) => toggle(&mut state.selection.edges, edge),
+                                    None => state.mode = Mode::Selecting(p, p, state.selection.clone())
+                                }
+                            });
+                        },
 
                         _ => ()
                     }
                 },
 
-                event::Widget::Drag(drag) if drag.button == input::MouseButton::Left => {
+                event::Widget::Press(event::Press {
+                    button: event::Button::Mouse(mouse::Button::Middle, _),
+                    ..
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        state.update(|state| state.mode = Mode::Panning(state.view));
+                    }
+                },
+
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::Space),
+                    ..
+                }) => state.update(|state| state.space_held = true),
+
+                event::Widget::Release(event::Release {
+                    button: event::Button::Keyboard(input::Key::Space),
+                    ..
+                }) => state.update(|state| state.space_held = false),
+
+                // zoom around the cursor
+                event::Widget::Scroll(scroll) => {
+                    if let Some(xy) = mouse_xy {
+                        state.update(|state| {
+                            let zoom = state.view.zoom;
+                            let factor = (zoom * ZOOM_STEP.powf(-scroll.y)).max(MIN_ZOOM).min(MAX_ZOOM) / zoom;
+                            state.view.zoom_around(in_widget_space(xy), factor);
+                        });
+                    }
+                },
+
+                event::Widget::Drag(drag) if drag.button == input::MouseButton::Left ||
+                                             drag.button == input::MouseButton::Middle => {
                     match &state.mode {
                         &Mode::Idle => (),
 
-                        // move vertex
-                        &Mode::MovingVertex(index, vpos) =>
+                        // pan
+                        &Mode::Panning(start) =>
                             state.update(|state| {
-                                let new_vpos = [vpos[0] + drag.total_delta_xy[0],
-                                                vpos[1] + drag.total_delta_xy[1]];
-                                let clamped = clamp(new_vpos, radius);
-                                (*state.graph.vertices[index]).position = clamped;
+                                state.view.pan = [start.pan[0] + drag.total_delta_xy[0],
+                                                  start.pan[1] + drag.total_delta_xy[1]];
                             }),
 
+                        _ if drag.button != input::MouseButton::Left => (),
+
+                        // move the selected vertices
+                        &Mode::Moving(ref starts) => {
+                            let starts = starts.clone();
+                            state.update(|state| {
+                                let zoom = state.view.zoom;
+                                for &(id, vpos) in starts.iter() {
+                                    let new_vpos = [vpos[0] + drag.total_delta_xy[0] / zoom,
+                                                    vpos[1] + drag.total_delta_xy[1] / zoom];
+                                    if let Some(v) = model.graph_mut().vertex_mut(id) {
+                                        v.position = new_vpos;
+                                    }
+                                }
+                            });
+                        },
+
+                        // select what's inside the box
+                        &Mode::Selecting(from, _, ref before) => {
+                            let before = before.clone();
+                            state.update(|state| {
+                                let to = state.view.to_graph(in_widget_space(drag.to));
+                                let (left, right) = (from[0].min(to[0]), from[0].max(to[0]));
+                                let (bottom, top) = (from[1].min(to[1]), from[1].max(to[1]));
+                                let inside = |p: Point| left <= p[0] && p[0] <= right && bottom <= p[1] && p[1] <= top;
+
+                                let mut selection = before.clone();
+                                let graph = &model.graph;
+                                let area = Bounds::new(left, right, bottom, top);
+                                let ids = state.index.vertices_in(&area).into_iter()
+                                    .filter(|&id| graph.vertex(id).map_or(false, |v| inside(v.position)));
+                                for id in ids {
+                                    selection.vertices.insert(id);
+                                    // edges count when both ends are inside
+                                    for &e in graph.vertex(id).unwrap().outs().iter() {
+                                        if inside(graph.vertex(graph.edge(e).unwrap().target()).unwrap().position) {
+                                            selection.edges.insert(e);
+                                        }
+                                    }
+                                }
+                                state.selection = selection;
+                                state.mode = Mode::Selecting(from, to, before);
+                            });
+                        },
+
                         // update edge preview
-                        &Mode::CreatingEdge(_, _, _, _) => {
+                        &Mode::CreatingEdge(_, _, _) => {
                             state.update(|state| {
-                                if let Mode::CreatingEdge(_, _, _, ref mut position) = state.mode {
-                                    *position = clamp(in_widget_space(drag.to), 0.0);
+                                if let Mode::CreatingEdge(_, _, ref mut position) = state.mode {
+                                    *position = state.view.to_graph(in_widget_space(drag.to));
                                 }
                             });
                         }
@@ -342,29 +888,34 @@ impl Widget for GraphWidget {
                 },
 
                 event::Widget::Release(release) => {
+                    // any mouse button ends panning
+                    if let (&Mode::Panning(_), event::Button::Mouse(_, _)) = (&state.mode, release.button) {
+                        state.update(|state| state.mode = Mode::Idle);
+                    }
+
                     // finish creating edge
                     if let event::Button::Mouse(input::MouseButton::Left, xy) = release.button {
                         match &state.mode {
-                            &Mode::CreatingEdge(src_idx, _, _, _) => {
-                                if let Some(target_idx) = vertex_at_point(&state, in_widget_space(xy)) {
-
+                            &Mode::CreatingEdge(source, _, _) => {
+                                let target = vertex_at_point(&state, &model.graph, in_graph_space(&state, xy));
+                                if let Some(target) = target.filter(|&t| dag && creates_cycle(&model.graph, source, t)) {
+                                    let label = |id| model.graph.vertex(id).unwrap().label.clone();
+                                    message = Some(format!("An edge from {} to {} would close a cycle", label(source), label(target)));
+                                    state.update(|state| state.mode = Mode::Idle);
+                                } else if let Some(target) = target {
                                     state.update(|state| {
-                                        let src_ptr: *mut Vertex = &mut *state.graph.vertices[src_idx];
-                                        let target_ptr: *mut Vertex = &mut *state.graph.vertices[target_idx];
-
-                                        // don't create redundant edges
-                                        if (*state.graph.vertices[src_idx])
-                                           .outs.iter().all(|&(p,_,_)| p != target_ptr) {
+                                        // the graph refuses redundant edges
+                                        if let Some(edge) = model.graph.add_edge(source, target) {
+                                            model.record(Change::AddEdge { id: edge, source: source, target: target });
 
                                             // steal the index slots from the preview
                                             let m = std::mem::replace(&mut state.mode, Mode::Idle);
-                                            let (line_slot, arrow_slot) = match m {
-                                                Mode::CreatingEdge(_, line_slot, arrow_slot, _) => (line_slot, arrow_slot),
+                                            let slots = match m {
+                                                Mode::CreatingEdge(_, slots, _) => slots,
                                                 _ => unreachable!()
                                             };
 
-                                            state.graph.vertices[src_idx].outs.push((target_ptr, line_slot, arrow_slot));
-                                            state.graph.vertices[target_idx].ins.push(src_ptr);
+                                            state.edge_slots.insert(edge, slots);
                                         }
 
                                         state.mode = Mode::Idle;
@@ -375,11 +926,29 @@ impl Widget for GraphWidget {
                                 }
                             },
 
-                            &Mode::MovingVertex(_,_) => {
-                                state.update(|state| state.mode = Mode::Idle);
+                            // the whole drag is a single step in the history
+                            &Mode::Moving(ref starts) => {
+                                let starts = starts.clone();
+                                state.update(|state| {
+                                    let moved: Vec<(VertexId, Point)> = starts.iter().cloned()
+                                        .filter(|&(id, from)| model.graph.vertex(id).unwrap().position != from)
+                                        .collect();
+                                    let mut changes: Vec<Change> = moved.into_iter().map(|(id, from)| {
+                                        let to = model.graph.vertex(id).unwrap().position;
+                                        Change::MoveVertex { id: id, from: from, to: to }
+                                    }).collect();
+                                    if changes.len() == 1 {
+                                        model.record(changes.pop().unwrap());
+                                    } else if changes.len() > 1 {
+                                        model.record(Change::Batch(changes));
+                                    }
+                                    state.mode = Mode::Idle;
+                                });
                             },
 
-                            &Mode::Idle => ()
+                            &Mode::Selecting(_, _, _) => state.update(|state| state.mode = Mode::Idle),
+
+                            &Mode::Panning(_) | &Mode::Idle => ()
                         }
                     }
                 },
@@ -392,94 +961,354 @@ impl Widget for GraphWidget {
                         // TODO: free index slots?
 
                         // remove vertex
-                        if let Some(vindex) = vertex_at_point(&state, in_widget_space(xy)) {
-                            let p: *const Vertex = &*state.graph.vertices[vindex];
+                        if let Some(id) = vertex_at_point(&state, &model.graph, in_graph_space(&state, xy)) {
                             state.update(|state| {
-                                for &(other, _, _) in state.graph.vertices[vindex].outs.iter() {
-                                    unsafe { (*other).ins.retain(|&q| p != q) };
-                                }
-                                for &other in state.graph.vertices[vindex].ins.iter() {
-                                    unsafe { (*other).outs.retain(|&(q,_,_)| p != q) };
+                                if let Some((vertex, edges)) = model.graph.remove_vertex(id) {
+                                    model.record(Change::RemoveVertex {
+                                        id: id,
+                                        label: vertex.label,
+                                        position: vertex.position,
+                                        edges: edges
+                                    });
                                 }
-
-                                state.graph.vertices.remove(vindex);
+                                forget_stale(state, &model.graph);
                             });
                         // remove edge
-                        } else if let Some((vindex, eindex)) = edge_at_point(&state, in_widget_space(xy)) {
+                        } else if let Some(edge) = edge_at_point(&state, &model.graph, in_graph_space(&state, xy), 6.0 / state.view.zoom) {
+                            // clickable a bit wider than drawn, whatever the zoom
                             state.update(|state| {
-                                let src_ptr: *const Vertex = &mut *state.graph.vertices[vindex];
-
-                                for &other in state.graph.vertices[vindex].ins.iter() {
-                                    unsafe {
-                                        (*other).ins.retain(|&ptr| src_ptr != ptr);
-                                    }
+                                if let Some(removed) = model.graph.remove_edge(edge) {
+                                    model.record(Change::RemoveEdge {
+                                        id: edge,
+                                        source: removed.source(),
+                                        target: removed.target(),
+                                        label: removed.label
+                                    });
                                 }
-                                (*state.graph.vertices[vindex]).outs.remove(eindex);
+                                forget_stale(state, &model.graph);
                             });
                         }
                     }
-                }
+                },
+
+                // copy
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::C),
+                    modifiers: keyboard::CTRL
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        state.update(|state| copy_selection(state, &model.graph));
+                    }
+                },
+
+                // cut
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::X),
+                    modifiers: keyboard::CTRL
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        if !state.selection.vertices.is_empty() {
+                            state.update(|state| {
+                                copy_selection(state, &model.graph);
+                                delete_selection(state, model);
+                            });
+                        }
+                    }
+                },
+
+                // paste at the cursor, or in the middle if the cursor isn't over the widget
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::V),
+                    modifiers: keyboard::CTRL
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        let at = mouse_xy.map(|xy| in_widget_space(xy)).unwrap_or_else(|| rect.xy());
+                        state.update(|state| {
+                            let at = state.view.to_graph(at);
+                            if let Some(refused) = paste(state, model, at, dag) {
+                                message = Some(refused);
+                            }
+                        });
+                    }
+                },
+
+                // delete the selection
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::Delete),
+                    ..
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        if !state.selection.is_empty() {
+                            state.update(|state| delete_selection(state, model));
+                        }
+                    }
+                },
+
+                // undo
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::Z),
+                    modifiers: keyboard::CTRL
+                }) => {
+                    if let Mode::Idle = state.mode {
+                        state.update(|state| {
+                            if model.undo() {
+                                // in DAG mode, undoing mustn't bring back a cycle from before
+                                if let Some((source, target)) = if dag { cycle_edge(&model.graph) } else { None } {
+                                    model.redo();
+                                    message = Some(format!("Undoing that would bring back the cycle the edge from {} to {} closes",
+                                                           source, target));
+                                }
+                                forget_stale(state, &model.graph);
+                            }
+                        });
+                    }
+                },
+
+                // redo
+                event::Widget::Press(event::Press {
+                    button: event::Button::Keyboard(input::Key::Z),
+                    modifiers
+                }) if modifiers == keyboard::CTRL | keyboard::SHIFT => {
+                    if let Mode::Idle = state.mode {
+                        state.update(|state| {
+                            if model.redo() {
+                                if let Some((source, target)) = if dag { cycle_edge(&model.graph) } else { None } {
+                                    model.undo();
+                                    message = Some(format!("Redoing that would bring back the cycle the edge from {} to {} closes",
+                                                           source, target));
+                                }
+                                forget_stale(state, &model.graph);
+                            }
+                        });
+                    }
+                },
 
                 _ => {}
             }
         }
 
-        if let &Mode::CreatingEdge(index, ref line_slot, ref arrow_slot, target) = &state.mode {
-            let start = (*state.graph.vertices[index]).position;
-            draw_arrow(start, target, &mut ui, style, idx, &line_slot, &arrow_slot, 0.0);
+        let view = state.view;
+
+        if let &Mode::CreatingEdge(source, ref slots, target) = &state.mode {
+            let start = model.graph.vertex(source).unwrap().position;
+            let edge_color = style.edge_color(&ui.theme);
+            let arrow = geometry::arrow(start, target, 0.0, arrow_base, arrow_height).map(|p| view.to_screen(p));
+            draw_arrow(&arrow, &mut ui, edge_color, idx, slots, view.zoom, None);
+        }
+
+        // layouts arrange the graph within what's visible
+        let bottom_left = view.to_graph([rect.left(), rect.bottom()]);
+        let top_right = view.to_graph([rect.right(), rect.top()]);
+        let bounds = Bounds::new(bottom_left[0], top_right[0], bottom_left[1], top_right[1]);
+
+        if let Some(layout) = self.layout {
+            state.update(|state| {
+                let before = model.graph.clone();
+                match layout {
+                    Layout::Force => ForceLayout::new(radius, bounds).run(&mut model.graph),
+                    Layout::Layered(direction) =>
+                        LayeredLayout::new(radius, bounds, direction).run(&mut model.graph)
+                }
+                if model.graph != before {
+                    let after = Box::new(model.graph.clone());
+                    model.record(Change::Replace { before: Box::new(before), after: after });
+                }
+            });
+        }
+
+        if self.live_layout {
+            // vertices that are being dragged stay under the mouse
+            let pinned: Vec<VertexId> = match state.mode {
+                Mode::Moving(ref starts) => starts.iter().map(|&(id, _)| id).collect(),
+                _ => vec![]
+            };
+            let mut layout = ForceLayout::new(radius, bounds);
+            // small steps, so the movement is easy to follow
+            layout.temperature = radius / 5.0;
+            state.update(|_| {
+                let before: Vec<(VertexId, Point)> = model.graph.vertices().map(|(id, v)| (id, v.position)).collect();
+                if layout.step(&mut model.graph, &pinned) > LIVE_LAYOUT_SETTLED {
+                    model.revision += 1;
+                } else {
+                    for (id, position) in before {
+                        model.graph.vertex_mut(id).unwrap().position = position;
+                    }
+                }
+            });
         }
 
         let vertex_outline_color = style.vertex_outline_color(&ui.theme);
         let vertex_fill_color = style.vertex_fill_color(&ui.theme);
-        
-        state.update(|state| { // need mutation for the TextBox
-            for v in state.graph.vertices.iter_mut() {
+        let edge_color = style.edge_color(&ui.theme);
+        let selection_color = style.selection_color(&ui.theme);
+
+        let show_analysis = self.analysis.is_some();
+        let revision = model.revision();
+        let stale = state.analyzed.as_ref().map(|&(analyzed, _)| analyzed) != Some(revision);
+        let mut report = None;
+        if show_analysis && stale {
+            let analysis = Analysis::new(&model.graph);
+            let mut component_colors: HashMap<VertexId, color::Color> = HashMap::new();
+            let cyclic = (0..analysis.components.len()).filter(|&c| analysis.is_cyclic(&model.graph, c));
+            for (n, c) in cyclic.enumerate() {
+                let color = COMPONENT_COLORS[n % COMPONENT_COLORS.len()];
+                for &id in analysis.components[c].iter() {
+                    component_colors.insert(id, color);
+                }
+            }
+            report = Some(analysis.report(&model.graph));
+            state.update(|state| state.analyzed = Some((revision, component_colors)));
+        }
+
+        state.update(|state| { // new vertices and edges need index slots
+            catch_up(state, model, radius);
+            let graph = &mut model.graph;
+            // label edits are recorded once everything is drawn
+            let mut edits = vec![];
+            let State { ref mut vertex_slots, ref mut edge_slots, ref selection, ref index, ref analyzed,
+                        ref mut typing, .. } = *state;
+            let component_colors = if show_analysis { analyzed.as_ref().map(|&(_, ref colors)| colors) } else { None };
+
+            // Only what's on screen is drawn. Labels can stick out of their vertex
+            // or edge, so a bit around the screen counts too.
+            let margin = 3.0 * radius;
+            let visible = Bounds::new(bounds.left - margin, bounds.right + margin,
+                                      bounds.bottom - margin, bounds.top + margin);
+            let visible_vertices: HashSet<VertexId> = index.vertices_in(&visible).into_iter().collect();
+            let visible_edges: HashSet<EdgeId> = index.edges_in(&visible).into_iter().collect();
+            // vertices are visited in creation order, each drawing its outgoing edges first
+            let mut order: BTreeSet<VertexId> = visible_vertices.iter().cloned().collect();
+            order.extend(visible_edges.iter().map(|&e| graph.edge(e).unwrap().source()));
+
+            for id in order {
 
                 // draw outgoing edges
-                for &(ref v2, ref line_idx, ref tip_idx) in v.outs.iter() {
-                    draw_arrow(v.position, unsafe { (**v2).position }, &mut ui,
-                               style, idx, line_idx, tip_idx, radius);
+                let mut label_edits = vec![];
+                {
+                    let v = graph.vertex(id).unwrap();
+                    for &e in v.outs().iter().filter(|&&e| visible_edges.contains(&e)) {
+                        let edge = graph.edge(e).unwrap();
+                        let arrow = geometry::edge_arrow(graph, e, radius, arrow_base, arrow_height).unwrap()
+                            .map(|p| view.to_screen(p));
+                        let slots = edge_slots.entry(e).or_insert_with(EdgeSlots::new);
+                        let selected = selection.edges.contains(&e);
+                        let color = if selected { selection_color } else { edge_color };
+                        // an edge without a label only offers a box to type one while it's selected
+                        let label = if selected || !edge.label.is_empty() { Some(edge.label.as_str()) } else { None };
+                        if let Some(string) = draw_arrow(&arrow, &mut ui, color, idx, slots, view.zoom, label) {
+                            label_edits.push((e, string, slots.label.get(&mut ui)));
+                        }
+                    }
                 }
+                for (e, string, typed) in label_edits {
+                    let edge = graph.edge_mut(e).unwrap();
+                    edits.push((typed, Change::SetEdgeLabel { id: e, from: edge.label.clone(), to: string.clone() }));
+                    edge.label = string;
+                }
+
+                if !visible_vertices.contains(&id) {
+                    continue;
+                }
+
+                let v = graph.vertex_mut(id).unwrap();
+                let slots = vertex_slots.entry(id).or_insert_with(VertexSlots::new);
 
                 // draw the vertex
-                primitive::shape::oval::Oval::fill([radius*2.0, radius*2.0])
-                    .xy(v.position)
-                    .color(vertex_fill_color)
+                let position = view.to_screen(v.position);
+                let size = radius * 2.0 * view.zoom;
+                primitive::shape::oval::Oval::fill([size, size])
+                    .xy(position)
+                    .color(component_colors.and_then(|colors| colors.get(&id)).cloned().unwrap_or(vertex_fill_color))
                     .graphics_for(idx)
                     .parent(idx)
-                    .set(v.fill_idx.get(&mut ui), &mut ui);
-
-                let linestyle = primitive::line::Style::new().thickness(2.0);
-                primitive::shape::oval::Oval::outline_styled([radius*2.0, radius*2.0], linestyle)
-                    .xy(v.position)
-                    .color(vertex_outline_color)
+                    .set(slots.fill.get(&mut ui), &mut ui);
+
+                // selected vertices get a thicker outline in the selection color
+                let (outline_color, thickness) = if selection.vertices.contains(&id) {
+                    (selection_color, 4.0)
+                } else {
+                    (vertex_outline_color, 2.0)
+                };
+                let linestyle = primitive::line::Style::new().thickness(thickness * view.zoom);
+                primitive::shape::oval::Oval::outline_styled([size, size], linestyle)
+                    .xy(position)
+                    .color(outline_color)
                     .graphics_for(idx)
                     .parent(idx)
-                    .set(v.outline_idx.get(&mut ui), &mut ui);
+                    .set(slots.outline.get(&mut ui), &mut ui);
 
-                let position = v.position.clone();
-                let i = v.text_idx.get(&mut ui);
-                let font_size = 12_u32;
+                let i = slots.text.get(&mut ui);
+                let font_size = ((12.0 * view.zoom).round() as u32).max(1);
                 let char_width = (font_size as f64) * 0.692;
-                let box_width = 30.0 + char_width * (v.label.len() as f64);
+                let box_width = 30.0 * view.zoom + char_width * (v.label.len() as f64);
                 for event in widget::text_box::TextBox::new(&mut v.label)
                     .xy(position)
-                    .wh([box_width, 25_f64])
+                    .wh([box_width, 25.0 * view.zoom])
                     .font_size(font_size)
                     .align_text_middle()
                     .parent(idx)
                     .set(i, &mut ui)
                 {
                     match event {
-                        widget::text_box::Event::Update(string) => v.label = string,
+                        widget::text_box::Event::Update(string) => {
+                            edits.push((i, Change::SetLabel { id: id, from: v.label.clone(), to: string.clone() }));
+                            v.label = string;
+                        },
                         _ => ()
                     }
                 }
             }
 
-            state.graph.vertices.sort_by(|a, b| a.label.cmp(&b.label));
+            for (typed, change) in edits {
+                model.record_typing(change);
+                *typing = Some(typed);
+            }
         });
 
-        graph_to_string(&state.graph)
+        if let Mode::Selecting(from, to, _) = state.mode {
+            let (from, to) = (view.to_screen(from), view.to_screen(to));
+            primitive::shape::rectangle::Rectangle::outline([(to[0] - from[0]).abs(), (to[1] - from[1]).abs()])
+                .xy([(from[0] + to[0]) / 2.0, (from[1] + to[1]) / 2.0])
+                .color(selection_color)
+                .graphics_for(idx)
+                .parent(idx)
+                .set(state.selection_box.get(&mut ui), &mut ui);
+        }
+
+        // typing into a label ends when its box lets go of the keyboard
+        if let Some(typed) = state.typing {
+            if ui.global_input().current.widget_capturing_keyboard != Some(typed) {
+                model.finish_typing();
+                state.update(|state| state.typing = None);
+            }
+        }
+
+        if let (Some(out), Some(message)) = (self.message, message) {
+            *out = message;
+        }
+
+        if let (Some(out), Some(report)) = (self.analysis, report) {
+            *out = report;
+        }
+
+        // the model counts its changes, so the graph only needs comparing when the count moved
+        let revision = model.revision();
+        if state.reported != Some(revision) {
+            events.extend(changes(&state.reported_graph, &model.graph));
+            state.update(|state| {
+                state.reported = Some(revision);
+                state.reported_graph = model.graph.clone();
+            });
+        }
+
+        if state.selection != state.reported_selection {
+            events.push(Event::SelectionChanged {
+                vertices: state.selection.vertices.iter().cloned().collect(),
+                edges: state.selection.edges.iter().cloned().collect()
+            });
+            state.update(|state| state.reported_selection = state.selection.clone());
+        }
+
+        events
     }
 }
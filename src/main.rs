@@ -1,20 +1,220 @@
 #[macro_use] extern crate conrod;
 extern crate piston_window;
 extern crate find_folder;
+extern crate adele;
+#[cfg(feature = "clipboard")]
+extern crate clipboard;
 
 use conrod::widget::primitive::shape::rectangle::Rectangle;
 use conrod::widget::primitive::text::Text;
-use conrod::widget::{Canvas, Widget, TextEdit};
-use conrod::Sizeable;
-use conrod::color;
-use piston_window::{EventLoop, OpenGL, PistonWindow, UpdateEvent, WindowSettings};
+use conrod::widget::{Button, Canvas, DropDownList, Widget, TextBox, TextEdit, Toggle};
+use conrod::{Labelable, Sizeable};
+use conrod::{color, event, input};
+use piston_window::{EventLoop, OpenGL, PistonWindow, ResizeEvent, UpdateEvent, WindowSettings};
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 mod graph_widget;
-use graph_widget::GraphWidget;
+use adele::analysis::first_cycle_edge;
+use adele::document::Document;
+use adele::dot::{graph_to_dot, parse_dot};
+use adele::graph::Graph;
+use adele::layout::layered::Direction;
+use adele::svg::{graph_to_svg, SvgStyle};
+use adele::text::{graph_to_string, parse_acyclic_graph, parse_graph, ParseError};
+use adele::types::Types;
+use graph_widget::{GraphWidget, Layout, Model, Style};
 
+// The size the window opens with.
 const WIDTH: u32 = 1080;
 const HEIGHT: u32 = 720;
 
+// The splitter keeps at least this much room on either side of it.
+const MIN_GRAPH_WIDTH: f64 = 300.0;
+const MIN_TEXT_WIDTH: f64 = 200.0;
+
+/// The contents of the GRAPH_TEXT pane, kept in sync with the graph canvas.
+struct GraphText {
+    text: String,
+    // The graph as it was written out last, so that canvas edits can be noticed.
+    last_emitted: String,
+    // The model revision it was written out for.
+    revision: Option<u64>,
+    // A graph parsed from an edit to `text` that the graph widget hasn't seen yet.
+    pending: Option<Graph>,
+    // Set once the widget synced with an edit, until typing into the pane ends.
+    typing: bool,
+    error: Option<ParseError>
+}
+
+/// The file being edited and the state of the toolbar that opens and saves it.
+struct DocumentFile {
+    path: Option<PathBuf>,
+    // Contents of the path box in the toolbar.
+    path_text: String,
+    status: String,
+    // A graph read from disk that hasn't replaced the model's graph yet.
+    loaded: Option<Graph>,
+    // Set when the loaded graph came without positions and needs a layout.
+    unplaced: bool,
+    // DAG mode, which refuses edits that would close a cycle.
+    dag: bool
+}
+
+impl DocumentFile {
+    fn new() -> Self {
+        DocumentFile {
+            path: None,
+            path_text: String::new(),
+            status: String::new(),
+            loaded: None,
+            unplaced: false,
+            dag: false
+        }
+    }
+
+    /// Opens the file in the path box. DOT files only hold a graph, so the
+    /// type text and DAG mode are left alone when opening one.
+    fn open(&mut self, typetext: &mut String) {
+        let path = PathBuf::from(self.path_text.trim());
+        let result = if is_dot(&path) {
+            let dag = self.dag;
+            read_file(&path).map_err(|e| e.to_string())
+                .and_then(|text| parse_dot(&text).map_err(|e| e.to_string()))
+                .and_then(|graph| match first_cycle_edge(&graph) {
+                    // DAG mode stays on, so the graph has to fit it
+                    Some(edge) if dag => {
+                        let e = graph.edge(edge).unwrap();
+                        let label = |id| &graph.vertex(id).unwrap().label;
+                        Err(format!("`{} -> {}` closes a cycle", label(e.source()), label(e.target())))
+                    },
+                    _ => Ok(graph)
+                })
+                .map(|graph| Document { dag: dag, ..Document::new(graph, typetext.clone()) })
+        } else {
+            Document::load(&path).map_err(|e| e.to_string())
+        };
+
+        match result {
+            Ok(document) => {
+                self.unplaced = document.graph.vertex_count() > 1 &&
+                    document.graph.vertices().all(|(_, v)| v.position == [0.0, 0.0]);
+                self.loaded = Some(document.graph);
+                *typetext = document.types;
+                self.dag = document.dag;
+                self.status = format!("Opened {}", path.display());
+                self.path = Some(path);
+            },
+            Err(e) => self.status = format!("Couldn't open {}: {}", path.display(), e)
+        }
+    }
+
+    /// Saves the document, or only its graph to a DOT file, or a picture of the graph to an SVG file.
+    /// An SVG file is an export, so Save keeps writing to the document afterwards.
+    fn save(&mut self, path: PathBuf, document: Document, svg_style: &SvgStyle) {
+        let result = if is_svg(&path) {
+            write_file(&path, &graph_to_svg(&document.graph, svg_style)).map_err(|e| e.to_string())
+        } else if is_dot(&path) {
+            write_file(&path, &graph_to_dot(&document.graph, true)).map_err(|e| e.to_string())
+        } else {
+            document.save(&path).map_err(|e| e.to_string())
+        };
+
+        match result {
+            Ok(()) if is_svg(&path) => self.status = format!("Exported {}", path.display()),
+            Ok(()) => {
+                self.status = if is_dot(&path) {
+                    format!("Saved {} (without the type text or DAG mode)", path.display())
+                } else {
+                    format!("Saved {}", path.display())
+                };
+                self.path_text = path.to_string_lossy().into_owned();
+                self.path = Some(path);
+            },
+            Err(e) => self.status = format!("Couldn't save {}: {}", path.display(), e)
+        }
+    }
+}
+
+fn is_dot(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("dot") | Some("gv") => true,
+        _ => false
+    }
+}
+
+fn is_svg(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("svg")
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+fn write_file(path: &Path, text: &str) -> io::Result<()> {
+    File::create(path)?.write_all(text.as_bytes())
+}
+
+/// Problems with the TYPE_TEXT pane, and vertex labels that don't fit it.
+struct TypeCheck {
+    // The model revision the diagnostics are about, or `None` once the type text changed.
+    checked: Option<u64>,
+    diagnostics: Vec<String>
+}
+
+impl TypeCheck {
+    fn is_stale(&self, revision: u64) -> bool {
+        self.checked != Some(revision)
+    }
+
+    fn check(&mut self, typetext: &str, graph: &Graph) {
+        let (types, errors) = Types::parse(typetext);
+        self.diagnostics = errors.iter().map(|e| e.to_string()).collect();
+        self.diagnostics.extend(types.check_graph(graph));
+    }
+}
+
+/// The panel over the graph canvas that reports on the graph's structure.
+struct AnalysisPanel {
+    open: bool,
+    report: String
+}
+
+/// How the window is divided between the graph and the text panes.
+struct Panes {
+    // The share of the window's width that goes to the graph, moved by dragging the splitter.
+    split: f64
+}
+
+/// The layout controls in the toolbar.
+struct LayoutTools {
+    // Index into `LAYOUTS` of the layout the Layout button applies.
+    choice: usize,
+    live: bool
+}
+
+// How many problems with the types are listed under the TYPE_TEXT pane.
+const MAX_DIAGNOSTICS: usize = 4;
+
+const LAYOUTS: [(&'static str, Layout); 3] = [
+    ("Force", Layout::Force),
+    ("Layers down", Layout::Layered(Direction::TopDown)),
+    ("Layers right", Layout::Layered(Direction::LeftRight))
+];
+
+
+fn example_graph() -> Graph {
+    let mut graph = Graph::new();
+    let v0 = graph.add_vertex("Hello world!".to_string(), [-200.0, -100.0]);
+    let v1 = graph.add_vertex("Holy smokes!".to_string(), [-200.0, 100.0]);
+    graph.add_edge(v1, v0);
+    graph
+}
+
 fn main() {
 
     let opengl = OpenGL::V3_2;
@@ -36,14 +236,41 @@ fn main() {
 
     let image_map = conrod::image::Map::new();
 
-    let mut typetext = "Type text goes here".to_string();
+    let mut typetext = "# Declare vertex types here, like\n# type Person, City;\n# and give vertices a type with labels like `alice (Person)`.\n".to_string();
+    let mut graphtext = GraphText {
+        text: String::new(),
+        last_emitted: String::new(),
+        revision: None,
+        pending: None,
+        typing: false,
+        error: None
+    };
+
+    let mut model = Model::new(example_graph());
+    let mut layout_tools = LayoutTools { choice: 0, live: false };
+    let mut analysis = AnalysisPanel { open: false, report: String::new() };
+    let mut typecheck = TypeCheck { checked: None, diagnostics: vec![] };
+    let mut panes = Panes { split: 0.7 };
+
+    let mut file = DocumentFile::new();
+    if let Some(path) = std::env::args().nth(1) {
+        file.path_text = path;
+        file.open(&mut typetext);
+    }
 
     while let Some(event) = window.next()  {
         if let Some(e) = conrod::backend::piston_window::convert_event(event.clone(), &window) {
             ui.handle_event(e);
         }
 
-        event.update(|_| set_ui(&mut ui.set_widgets(), &mut typetext));
+        // text is drawn through a texture the size of the window
+        if let Some(size) = event.resize_args() {
+            text_texture_cache =
+                conrod::backend::piston_window::GlyphCache::new(&mut window, size[0], size[1]);
+        }
+
+        event.update(|_| set_ui(&mut ui.set_widgets(), &mut model, &mut typetext, &mut graphtext, &mut file,
+                                &mut layout_tools, &mut analysis, &mut typecheck, &mut panes));
 
         window.draw_2d(&event, |c, g| {
             if let Some(primitives) = ui.draw_if_changed() {
@@ -58,56 +285,324 @@ fn main() {
 }
 
 
-fn set_ui(ui: &mut conrod::UiCell, typetext: &mut String) {
+fn set_ui(ui: &mut conrod::UiCell, model: &mut Model, typetext: &mut String, graphtext: &mut GraphText,
+          file: &mut DocumentFile, layout_tools: &mut LayoutTools, analysis: &mut AnalysisPanel,
+          typecheck: &mut TypeCheck, panes: &mut Panes) {
     use conrod::{Colorable, Positionable};
 
-    let hdivide = 0.7_f64;
-    let left_width = hdivide * (WIDTH as f64);
-    let right_width = (1.0 - hdivide) * (WIDTH as f64);
+    // everything is laid out from the window's current size
+    let win_w = ui.win_w;
+    let win_h = ui.win_h;
+
+    let splitter_width = 6.0;
+    let widest = (win_w - splitter_width - MIN_TEXT_WIDTH).max(0.0);
+    let graph_width = |split: f64| (split * win_w).min(widest).max(MIN_GRAPH_WIDTH.min(widest));
+    for event in ui.widget_input(SPLITTER).events() {
+        if let event::Widget::Drag(drag) = event {
+            if drag.button == input::MouseButton::Left {
+                // stay within the limits, so dragging back out of them takes effect right away
+                panes.split = graph_width(panes.split + drag.delta_xy[0] / win_w) / win_w;
+            }
+        }
+    }
+
+    let left_width = graph_width(panes.split);
+    let right_width = (win_w - left_width - splitter_width).max(0.0);
 
     let vdivide = 0.6_f64;
     let vgap = 6.0;
-    let top_height = vdivide * (HEIGHT as f64) - 3.0*vgap/2.0;
-    let bottom_height = (1.0 - vdivide) * (HEIGHT as f64) - 3.0*vgap/2.0;
+    let top_height = vdivide * win_h - 3.0*vgap/2.0;
+    let bottom_height = (1.0 - vdivide) * win_h - 3.0*vgap/2.0;
 
     let textmargin = 10.0;
     let fontsize = 12_u32;
 
+    let toolbar_height = 36.0;
+    let button_height = 24.0;
+    let button_gap = 6.0;
+
     Canvas::new()
         .color(color::rgb(0.97, 0.97, 0.97))
         .set(MASTER, ui);
 
-    let graphtext: String = GraphWidget::new()
-        .mid_left_of(MASTER)
-        .w_h(left_width, 720_f64)
+    Rectangle::fill_with([left_width, toolbar_height], color::rgb(0.9, 0.9, 0.9))
+        .top_left_of(MASTER)
+        .set(TOOLBAR_BG, ui);
+
+    for event in TextBox::new(&mut file.path_text)
+        .mid_left_with_margin_on(TOOLBAR_BG, button_gap)
+        .w_h(110.0, button_height)
+        .font_size(fontsize)
+        .set(PATH_BOX, ui)
+    {
+        if let conrod::widget::text_box::Event::Update(path) = event {
+            file.path_text = path;
+        }
+    }
+
+    let open_clicked = Button::new()
+        .label("Open")
+        .right_from(PATH_BOX, button_gap)
+        .w_h(60.0, button_height)
+        .label_font_size(fontsize)
+        .set(OPEN_BUTTON, ui)
+        .was_clicked();
+
+    let save_clicked = Button::new()
+        .label("Save")
+        .right_from(OPEN_BUTTON, button_gap)
+        .w_h(60.0, button_height)
+        .label_font_size(fontsize)
+        .set(SAVE_BUTTON, ui)
+        .was_clicked();
+
+    let save_as_clicked = Button::new()
+        .label("Save As")
+        .right_from(SAVE_BUTTON, button_gap)
+        .w_h(80.0, button_height)
+        .label_font_size(fontsize)
+        .set(SAVE_AS_BUTTON, ui)
+        .was_clicked();
+
+    let layout_names: Vec<String> = LAYOUTS.iter().map(|&(name, _)| name.to_string()).collect();
+    for choice in DropDownList::new(&layout_names, Some(layout_tools.choice))
+        .right_from(SAVE_AS_BUTTON, 2.0*button_gap)
+        .w_h(110.0, button_height)
+        .label_font_size(fontsize)
+        .set(LAYOUT_LIST, ui)
+    {
+        layout_tools.choice = choice;
+    }
+
+    let layout_clicked = Button::new()
+        .label("Layout")
+        .right_from(LAYOUT_LIST, button_gap)
+        .w_h(70.0, button_height)
+        .label_font_size(fontsize)
+        .set(LAYOUT_BUTTON, ui)
+        .was_clicked();
+
+    for live in Toggle::new(layout_tools.live)
+        .label("Live")
+        .right_from(LAYOUT_BUTTON, button_gap)
+        .w_h(60.0, button_height)
+        .label_font_size(fontsize)
+        .set(LIVE_LAYOUT_TOGGLE, ui)
+    {
+        layout_tools.live = live;
+    }
+
+    for open in Toggle::new(analysis.open)
+        .label("Analysis")
+        .right_from(LIVE_LAYOUT_TOGGLE, 2.0*button_gap)
+        .w_h(80.0, button_height)
+        .label_font_size(fontsize)
+        .set(ANALYSIS_TOGGLE, ui)
+    {
+        analysis.open = open;
+    }
+
+    for dag in Toggle::new(file.dag)
+        .label("DAG")
+        .right_from(ANALYSIS_TOGGLE, button_gap)
+        .w_h(50.0, button_height)
+        .label_font_size(fontsize)
+        .set(DAG_TOGGLE, ui)
+    {
+        // a DAG document with a cycle couldn't be opened again, so the cycle has to go first
+        match if dag { first_cycle_edge(model.graph()) } else { None } {
+            Some(edge) => {
+                let graph = model.graph();
+                let e = graph.edge(edge).unwrap();
+                let label = |id| &graph.vertex(id).unwrap().label;
+                file.status = format!("DAG mode needs a graph without cycles, but `{} => {}` closes one",
+                                      label(e.source()), label(e.target()));
+            },
+            None => file.dag = dag
+        }
+    }
+
+    if open_clicked {
+        file.open(typetext);
+        // an unfinished text edit shouldn't be applied on top of the new document
+        graphtext.pending = None;
+    }
+
+    // Save writes to the file that was opened or saved last, Save As to the path box.
+    let save_to = match (save_clicked, save_as_clicked, file.path.clone()) {
+        (true, false, Some(path)) => Some(path),
+        (true, _, _) | (_, true, _) if file.path_text.trim().is_empty() => {
+            file.status = "Type a file name first".to_string();
+            None
+        },
+        (true, _, _) | (_, true, _) => Some(PathBuf::from(file.path_text.trim())),
+        _ => None
+    };
+
+    let loaded = file.loaded.take();
+    let layout = if layout_clicked {
+        Some(LAYOUTS[layout_tools.choice].1)
+    } else if loaded.is_some() && file.unplaced {
+        Some(Layout::Layered(Direction::TopDown))
+    } else {
+        None
+    };
+    if let Some(graph) = loaded {
+        model.replace(graph);
+    }
+    let from_text = graphtext.pending.take();
+    graphtext.typing |= from_text.is_some();
+    let mut message = String::new();
+    GraphWidget::new(model)
+        .sync_with(from_text.as_ref())
+        .layout(layout)
+        .live_layout(layout_tools.live)
+        .analysis(if analysis.open { Some(&mut analysis.report) } else { None })
+        .dag(file.dag)
+        .message(Some(&mut message))
+        .crop_kids()
+        .bottom_left_of(MASTER)
+        .w_h(left_width, win_h - toolbar_height)
         .set(GRAPH, ui);
 
+    if !message.is_empty() {
+        file.status = message;
+    }
+
+    // the graph can only have changed since it was last written out if the model counted a change
+    let revision = model.revision();
+    if graphtext.revision != Some(revision) {
+        let emitted = graph_to_string(model.graph());
+        // the edit might not have changed the text
+        if emitted != graphtext.last_emitted {
+            // Changes that came from the text pane aren't written back, so that
+            // the text isn't reformatted while it's being typed.
+            if from_text.is_none() {
+                graphtext.text = emitted.clone();
+                graphtext.error = None;
+            }
+            graphtext.last_emitted = emitted;
+        }
+        graphtext.revision = Some(revision);
+    }
+
+    // the graph only needs to be looked at when it or the types changed
+    if typecheck.is_stale(model.revision()) {
+        typecheck.check(typetext, model.graph());
+        typecheck.checked = Some(model.revision());
+    }
+
+    if let Some(path) = save_to {
+        let svg_style = Style::new().svg_style(&ui.theme);
+        let document = Document { dag: file.dag, ..Document::new(model.graph().clone(), typetext.clone()) };
+        file.save(path, document, &svg_style);
+    }
+
+    Text::new(&file.status)
+        .bottom_left_with_margin_on(GRAPH, textmargin)
+        .font_size(fontsize)
+        .color(color::BLACK)
+        .set(STATUS_TEXT, ui);
+
+    if analysis.open {
+        // Lines that are too long for the panel wrap, so count them as several.
+        let panel_width = 260.0;
+        let text_width = panel_width - 2.0*textmargin;
+        let char_width = fontsize as f64 * 0.692;
+        let lines: f64 = analysis.report.lines()
+            .map(|line| (line.chars().count() as f64 * char_width / text_width).ceil().max(1.0))
+            .sum();
+        let panel_height = lines * 1.25 * fontsize as f64 + 2.0*textmargin;
+
+        Rectangle::fill_with([panel_width, panel_height], color::rgba(0.9, 0.9, 0.9, 0.9))
+            .top_right_with_margin_on(GRAPH, textmargin)
+            .set(ANALYSIS_BG, ui);
+
+        Text::new(&analysis.report)
+            .top_left_with_margin_on(ANALYSIS_BG, textmargin)
+            .w(text_width)
+            .font_size(fontsize)
+            .color(color::BLACK)
+            .set(ANALYSIS_TEXT, ui);
+    }
+
+    Rectangle::fill_with([splitter_width, win_h], color::rgb(0.8, 0.8, 0.8))
+        .top_left_with_margins_on(MASTER, 0.0, left_width)
+        .set(SPLITTER, ui);
+
     Rectangle::fill_with([right_width, top_height], color::rgb(0.9, 0.9, 0.9))
         .top_right_with_margins_on(MASTER, vgap, 0.0)
         .set(TYPE_TEXT_BG, ui);
 
+    // the diagnostics take up the bottom of the pane, as many lines as they need up to a limit
+    let mut diagnostics: Vec<String> = typecheck.diagnostics.iter().take(MAX_DIAGNOSTICS).cloned().collect();
+    if typecheck.diagnostics.len() > MAX_DIAGNOSTICS {
+        diagnostics.push(format!("and {} more", typecheck.diagnostics.len() - MAX_DIAGNOSTICS));
+    }
+    let diagnostics_height = if diagnostics.is_empty() {
+        0.0
+    } else {
+        diagnostics.len() as f64 * 1.25 * fontsize as f64 + textmargin
+    };
+
     for edit in TextEdit::new(typetext)
         .top_right_with_margin_on(TYPE_TEXT_BG, textmargin)
-        .w_h(right_width - 2.0*textmargin, top_height - 2.0*textmargin)
+        .w_h(right_width - 2.0*textmargin, top_height - 2.0*textmargin - diagnostics_height)
         .font_size(fontsize)
         .color(color::BLACK)
         .set(TYPE_TEXT, ui)
     {
         *typetext = edit;
+        typecheck.checked = None;
+    }
+
+    if !diagnostics.is_empty() {
+        Text::new(&diagnostics.join("\n"))
+            .bottom_left_with_margin_on(TYPE_TEXT_BG, textmargin)
+            .w(right_width - 2.0*textmargin)
+            .font_size(fontsize)
+            .color(color::DARK_RED)
+            .set(TYPE_TEXT_DIAGNOSTICS, ui);
     }
 
     Rectangle::fill_with([right_width, bottom_height], color::rgb(0.9, 0.9, 0.9))
         .bottom_right_with_margins_on(MASTER, vgap, 0.0)
         .set(GRAPH_TEXT_BG, ui);
 
-    Text::new(&graphtext[..])
+    let error_height = if graphtext.error.is_some() { fontsize as f64 + textmargin } else { 0.0 };
+
+    for edit in TextEdit::new(&graphtext.text)
         .top_right_with_margin_on(GRAPH_TEXT_BG, textmargin)
-        .w_h(right_width - 2.0*textmargin, bottom_height - 2.0*textmargin)
+        .w_h(right_width - 2.0*textmargin, bottom_height - 2.0*textmargin - error_height)
         .font_size(fontsize)
         .color(color::BLACK)
-        .wrap_by_word()
-        .align_text_left()
-        .set(GRAPH_TEXT, ui);
+        .set(GRAPH_TEXT, ui)
+    {
+        let parsed = if file.dag { parse_acyclic_graph(&edit) } else { parse_graph(&edit) };
+        match parsed {
+            Ok(graph) => {
+                graphtext.pending = Some(graph);
+                graphtext.error = None;
+            },
+            Err(error) => graphtext.error = Some(error)
+        }
+        graphtext.text = edit;
+    }
+
+    // the syncs from the text undo as one step, up to when the pane lets go of the keyboard
+    if graphtext.typing && ui.global_input().current.widget_capturing_keyboard != Some(GRAPH_TEXT.into()) {
+        model.finish_typing();
+        graphtext.typing = false;
+    }
+
+    if let Some(ref error) = graphtext.error {
+        Text::new(&error.to_string())
+            .bottom_left_with_margin_on(GRAPH_TEXT_BG, textmargin)
+            .w(right_width - 2.0*textmargin)
+            .font_size(fontsize)
+            .color(color::DARK_RED)
+            .set(GRAPH_TEXT_ERROR, ui);
+    }
 }
 
 widget_ids! {
@@ -115,6 +610,22 @@ widget_ids! {
     GRAPH,
     TYPE_TEXT_BG,
     TYPE_TEXT,
+    TYPE_TEXT_DIAGNOSTICS,
     GRAPH_TEXT_BG,
-    GRAPH_TEXT
+    GRAPH_TEXT,
+    GRAPH_TEXT_ERROR,
+    TOOLBAR_BG,
+    PATH_BOX,
+    OPEN_BUTTON,
+    SAVE_BUTTON,
+    SAVE_AS_BUTTON,
+    LAYOUT_LIST,
+    LAYOUT_BUTTON,
+    LIVE_LAYOUT_TOGGLE,
+    STATUS_TEXT,
+    ANALYSIS_TOGGLE,
+    ANALYSIS_BG,
+    ANALYSIS_TEXT,
+    DAG_TOGGLE,
+    SPLITTER
 }